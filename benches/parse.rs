@@ -0,0 +1,84 @@
+extern crate cookie;
+extern crate test;
+
+use std::borrow::Cow;
+use std::string::CowString;
+
+use cookie::{Cookie, CookieRef};
+use test::Bencher;
+
+const SIMPLE: &'static str = "session=0123456789abcdef0123456789abcdef";
+const ATTRS: &'static str = "session=0123456789abcdef0123456789abcdef; \
+                             HttpOnly; Secure; Path=/app; Domain=example.com; \
+                             Max-Age=3600";
+const ENCODED: &'static str = "redirect=%2Fapp%2Fsettings%3Ftab%3D2; Path=/";
+const CUSTOM: &'static str = "session=0123456789abcdef0123456789abcdef; \
+                              Path=/; Version=1; Comment=none";
+
+// `Cookie::parse` copies the name, value, path, domain and every custom
+// attribute into new `String`s. `CookieRef::parse` only copies those which
+// are percent-decoded, so these inputs are parsed without allocating them.
+#[test]
+fn borrows_undecoded() {
+    for s in [SIMPLE, ATTRS, CUSTOM].iter() {
+        let c = CookieRef::parse(*s).unwrap();
+        assert!(borrowed(&c.name) && borrowed(&c.value));
+        assert!(c.custom.iter().all(|&(ref k, ref v)| borrowed(k) && borrowed(v)));
+        assert_eq!(c.into_owned(), Cookie::parse(*s).unwrap());
+    }
+    let c = CookieRef::parse(ATTRS).unwrap();
+    assert_eq!(c.path, Some("/app"));
+    assert_eq!(c.domain, Some("example.com"));
+    assert_eq!(CookieRef::parse(CUSTOM).unwrap().custom.len(), 2);
+
+    let c = CookieRef::parse(ENCODED).unwrap();
+    assert!(borrowed(&c.name) && !borrowed(&c.value));
+    assert_eq!(c.into_owned(), Cookie::parse(ENCODED).unwrap());
+
+    fn borrowed(s: &CowString) -> bool {
+        match *s {
+            Cow::Borrowed(..) => true,
+            Cow::Owned(..) => false,
+        }
+    }
+}
+
+#[bench]
+fn owned_simple(b: &mut Bencher) {
+    b.iter(|| Cookie::parse(SIMPLE).unwrap());
+}
+
+#[bench]
+fn borrowed_simple(b: &mut Bencher) {
+    b.iter(|| CookieRef::parse(SIMPLE).unwrap());
+}
+
+#[bench]
+fn owned_attrs(b: &mut Bencher) {
+    b.iter(|| Cookie::parse(ATTRS).unwrap());
+}
+
+#[bench]
+fn borrowed_attrs(b: &mut Bencher) {
+    b.iter(|| CookieRef::parse(ATTRS).unwrap());
+}
+
+#[bench]
+fn owned_encoded(b: &mut Bencher) {
+    b.iter(|| Cookie::parse(ENCODED).unwrap());
+}
+
+#[bench]
+fn borrowed_encoded(b: &mut Bencher) {
+    b.iter(|| CookieRef::parse(ENCODED).unwrap());
+}
+
+#[bench]
+fn owned_custom(b: &mut Bencher) {
+    b.iter(|| Cookie::parse(CUSTOM).unwrap());
+}
+
+#[bench]
+fn borrowed_custom(b: &mut Bencher) {
+    b.iter(|| CookieRef::parse(CUSTOM).unwrap());
+}
@@ -0,0 +1,136 @@
+//! AES-256-GCM, which isn't exposed by the OpenSSL bindings.
+
+use libc::{c_int, c_uchar, c_void};
+use std::ptr;
+
+pub const KEY_LEN: uint = 32;
+pub const NONCE_LEN: uint = 12;
+pub const TAG_LEN: uint = 16;
+
+const EVP_CTRL_GCM_GET_TAG: c_int = 0x10;
+const EVP_CTRL_GCM_SET_TAG: c_int = 0x11;
+
+#[allow(non_camel_case_types)]
+enum EVP_CIPHER_CTX {}
+#[allow(non_camel_case_types)]
+enum EVP_CIPHER {}
+
+#[link(name = "crypto")]
+extern {
+    fn EVP_CIPHER_CTX_new() -> *mut EVP_CIPHER_CTX;
+    fn EVP_CIPHER_CTX_free(ctx: *mut EVP_CIPHER_CTX);
+    fn EVP_CIPHER_CTX_ctrl(ctx: *mut EVP_CIPHER_CTX, kind: c_int, arg: c_int,
+                           ptr: *mut c_void) -> c_int;
+    fn EVP_aes_256_gcm() -> *const EVP_CIPHER;
+    fn EVP_CipherInit_ex(ctx: *mut EVP_CIPHER_CTX, cipher: *const EVP_CIPHER,
+                         engine: *mut c_void, key: *const c_uchar,
+                         iv: *const c_uchar, enc: c_int) -> c_int;
+    fn EVP_CipherUpdate(ctx: *mut EVP_CIPHER_CTX, out: *mut c_uchar,
+                        outl: *mut c_int, input: *const c_uchar,
+                        inl: c_int) -> c_int;
+    fn EVP_CipherFinal_ex(ctx: *mut EVP_CIPHER_CTX, out: *mut c_uchar,
+                          outl: *mut c_int) -> c_int;
+}
+
+struct Context {
+    ctx: *mut EVP_CIPHER_CTX,
+}
+
+impl Context {
+    // `enc` is 1 to encrypt and 0 to decrypt.
+    fn new(key: &[u8], nonce: &[u8], enc: c_int) -> Context {
+        assert!(key.len() == KEY_LEN && nonce.len() == NONCE_LEN);
+        unsafe {
+            let ctx = Context { ctx: EVP_CIPHER_CTX_new() };
+            assert!(!ctx.ctx.is_null());
+            assert!(EVP_CipherInit_ex(ctx.ctx, EVP_aes_256_gcm(), ptr::null_mut(),
+                                      key.as_ptr(), nonce.as_ptr(), enc) == 1);
+            ctx
+        }
+    }
+
+    // Authenticates `aad`, then encrypts or decrypts `data`.
+    fn update(&self, aad: &[u8], data: &[u8]) -> Vec<u8> {
+        // GCM is a stream mode, so nothing is buffered.
+        let mut out = Vec::from_elem(data.len(), 0u8);
+        unsafe {
+            let mut len = 0;
+            assert!(EVP_CipherUpdate(self.ctx, ptr::null_mut(), &mut len,
+                                     aad.as_ptr(), aad.len() as c_int) == 1);
+            assert!(EVP_CipherUpdate(self.ctx, out.as_mut_ptr(), &mut len,
+                                     data.as_ptr(), data.len() as c_int) == 1);
+        }
+        out
+    }
+
+    fn finish(&self) -> bool {
+        let mut rest = [0u8, ..16];
+        let mut len = 0;
+        unsafe { EVP_CipherFinal_ex(self.ctx, rest.as_mut_ptr(), &mut len) == 1 }
+    }
+
+    fn ctrl(&self, kind: c_int, buf: &mut [u8]) {
+        unsafe {
+            assert!(EVP_CIPHER_CTX_ctrl(self.ctx, kind, buf.len() as c_int,
+                                        buf.as_mut_ptr() as *mut c_void) == 1);
+        }
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        unsafe { EVP_CIPHER_CTX_free(self.ctx) }
+    }
+}
+
+/// Encrypts `data`, returning the ciphertext followed by the tag, which also
+/// authenticates `aad`.
+pub fn seal(key: &[u8], nonce: &[u8], aad: &[u8], data: &[u8]) -> Vec<u8> {
+    let ctx = Context::new(key, nonce, 1);
+    let mut out = ctx.update(aad, data);
+    assert!(ctx.finish());
+    let mut tag = Vec::from_elem(TAG_LEN, 0u8);
+    ctx.ctrl(EVP_CTRL_GCM_GET_TAG, tag.as_mut_slice());
+    out.push_all(tag.as_slice());
+    out
+}
+
+/// Decrypts the output of `seal`, returning `None` if it or `aad` has been
+/// changed.
+pub fn open(key: &[u8], nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < TAG_LEN { return None }
+    let split = sealed.len() - TAG_LEN;
+    let ctx = Context::new(key, nonce, 0);
+    let out = ctx.update(aad, sealed.slice_to(split));
+    ctx.ctrl(EVP_CTRL_GCM_SET_TAG, sealed.slice_from(split).to_vec().as_mut_slice());
+    if ctx.finish() { Some(out) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::{FromHex, ToHex};
+    use super::{seal, open};
+
+    #[test]
+    fn known_answer() {
+        // Test case 14 of the GCM specification.
+        let key = [0u8, ..32];
+        let nonce = [0u8, ..12];
+        let sealed = seal(&key, &nonce, &[], &[0u8, ..16]);
+        assert_eq!(sealed.as_slice().to_hex().as_slice(),
+                   "cea7403d4d606b6e074ec5d3baf39d18d0d1c8a799996bf0265b98b5d48ab919");
+        assert_eq!(open(&key, &nonce, &[], sealed.as_slice()), Some(Vec::from_elem(16, 0u8)));
+    }
+
+    #[test]
+    fn tampering() {
+        let key = [0u8, ..32];
+        let nonce = [0u8, ..12];
+        let sealed = "7255b4e10071856b7165dcc2dba3a344".from_hex().unwrap();
+        assert_eq!(seal(&key, &nonce, b"name", &[]), sealed);
+        assert_eq!(open(&key, &nonce, b"name", sealed.as_slice()), Some(vec![]));
+        assert_eq!(open(&key, &nonce, b"other", sealed.as_slice()), None);
+        assert_eq!(open(&[1u8, ..32], &nonce, b"name", sealed.as_slice()), None);
+        assert_eq!(open(&key, &nonce, b"name", sealed.slice_from(1)), None);
+    }
+}
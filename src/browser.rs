@@ -0,0 +1,448 @@
+//! Conversions between `Cookie` and the JSON cookie formats used by browser
+//! automation tools.
+//!
+//! Each format describes a cookie as a JSON object with its own field names
+//! and its own way of writing the expiry time:
+//!
+//! * Playwright's `storageState` files use `expires` in (possibly
+//!   fractional) seconds since the Unix epoch, with `-1` for a session
+//!   cookie.
+//! * HAR files use `expires` as an ISO 8601 date-time, omitted for a session
+//!   cookie.
+//! * WebDriver uses `expiry` in whole seconds, omitted for a session cookie.
+//!
+//! All of them call the flags `httpOnly`, `secure` and `sameSite`. The
+//! `domain` field is kept as the tool wrote it, leading `.` and all. The
+//! `name` and `value` fields hold the text sent in the `Cookie` header, so
+//! they are percent-decoded when read and encoded when written, just as
+//! they are in a cookies.txt file.
+//!
+//! These are only compiled with the `serialize` feature.
+
+use std::collections::TreeMap;
+use std::error::Error;
+use std::fmt;
+use serialize::json;
+use serialize::json::Json;
+use time;
+use time::Timespec;
+
+use {Cookie, Expiration};
+use date;
+use parse::{decode_or_raw, unwrap_quotes};
+
+// The start of the year 10000, in seconds since the Unix epoch.
+const MAX_EXPIRY: f64 = 253402300800.0;
+
+/// A JSON cookie format.
+#[deriving(PartialEq, Eq, Clone, Copy, Show)]
+pub enum BrowserFormat {
+    /// Playwright's `storageState`, either the whole object or just its
+    /// `cookies` array.
+    Playwright,
+    /// A HAR file. Reading collects the cookies of every request and response
+    /// in `log.entries`, in order; a bare array of cookies is also accepted.
+    /// Writing produces a bare array, as found in an entry's `cookies`.
+    Har,
+    /// WebDriver cookie objects, either as an array or as the `value` of a
+    /// `Get All Cookies` response.
+    WebDriver,
+}
+
+/// Reads cookies from a document in the given format.
+///
+/// # Example
+///
+/// ```
+/// use cookie::{BrowserFormat, CookieJar, from_browser_json, to_browser_json};
+///
+/// let state = "{\"cookies\":[{\"name\":\"id\",\"value\":\"1\",\
+///              \"domain\":\"example.com\",\"path\":\"/\",\"expires\":-1,\
+///              \"httpOnly\":true,\"secure\":false,\"sameSite\":\"Lax\"}],\
+///              \"origins\":[]}";
+///
+/// let mut jar = CookieJar::new(b"f8f9eaf1ecdedff5e5b749c58115441e");
+/// for cookie in from_browser_json(state, BrowserFormat::Playwright)
+///                   .unwrap().into_iter() {
+///     jar.add_original(cookie);
+/// }
+/// jar.remove("id");
+///
+/// let out = to_browser_json(jar.delta().as_slice(), BrowserFormat::Playwright);
+/// ```
+pub fn from_browser_json(s: &str, format: BrowserFormat)
+                         -> Result<Vec<Cookie>, BrowserJsonError> {
+    let json = match json::from_str(s) {
+        Ok(json) => json,
+        Err(e) => return Err(BrowserJsonError::Syntax(e.to_string())),
+    };
+
+    let mut objects = Vec::new();
+    match (format, &json) {
+        (_, &Json::Array(ref a)) => objects.extend(a.iter()),
+        (BrowserFormat::Playwright, _) => {
+            match json.find("cookies").and_then(|c| c.as_array()) {
+                Some(a) => objects.extend(a.iter()),
+                None => return Err(BrowserJsonError::UnexpectedShape),
+            }
+        }
+        (BrowserFormat::WebDriver, _) => {
+            match json.find("value").and_then(|c| c.as_array()) {
+                Some(a) => objects.extend(a.iter()),
+                None => return Err(BrowserJsonError::UnexpectedShape),
+            }
+        }
+        (BrowserFormat::Har, _) => {
+            let entries = match json.find("log").and_then(|l| l.find("entries"))
+                                    .and_then(|e| e.as_array()) {
+                Some(e) => e,
+                None => return Err(BrowserJsonError::UnexpectedShape),
+            };
+            for entry in entries.iter() {
+                for part in ["request", "response"].iter() {
+                    match entry.find(*part).and_then(|p| p.find("cookies"))
+                               .and_then(|c| c.as_array()) {
+                        Some(a) => objects.extend(a.iter()),
+                        None => {}
+                    }
+                }
+            }
+        }
+    }
+
+    let mut cookies = Vec::new();
+    for (i, obj) in objects.into_iter().enumerate() {
+        cookies.push(try!(cookie_from_json(obj, format, i)));
+    }
+    Ok(cookies)
+}
+
+/// Writes cookies as a document in the given format.
+///
+/// The formats have no `Max-Age`, so each cookie's expiry is resolved as of
+/// the current time. A cookie which has already expired, such as a removal
+/// cookie from `CookieJar::delta`, is written with an expiry at the epoch so
+/// the browser deletes it.
+pub fn to_browser_json(cookies: &[Cookie], format: BrowserFormat) -> String {
+    let now = time::get_time();
+    let array = Json::Array(cookies.iter().map(|c| {
+        cookie_to_json(c, format, now)
+    }).collect());
+    match format {
+        BrowserFormat::Playwright => {
+            let mut state = TreeMap::new();
+            state.insert("cookies".to_string(), array);
+            state.insert("origins".to_string(), Json::Array(Vec::new()));
+            Json::Object(state).to_string()
+        }
+        BrowserFormat::Har | BrowserFormat::WebDriver => array.to_string(),
+    }
+}
+
+fn cookie_from_json(obj: &Json, format: BrowserFormat, index: uint)
+                    -> Result<Cookie, BrowserJsonError> {
+    if obj.as_object().is_none() {
+        return Err(BrowserJsonError::UnexpectedShape)
+    }
+    let name = match try!(string(obj, "name", index)) {
+        Some(s) => s,
+        None => return Err(BrowserJsonError::MissingField { index: index, field: "name" }),
+    };
+    let value = match try!(string(obj, "value", index)) {
+        Some(s) => s,
+        None => return Err(BrowserJsonError::MissingField { index: index, field: "value" }),
+    };
+    // The tools keep any quotes as part of the value.
+    let mut c = {
+        let (value, quoted) = unwrap_quotes(value.as_slice());
+        let mut c = Cookie::new(decode_or_raw(name.as_slice()), decode_or_raw(value));
+        c.quoted = quoted;
+        c
+    };
+    c.domain = try!(string(obj, "domain", index));
+    c.path = try!(string(obj, "path", index));
+    c.secure = try!(flag(obj, "secure", index));
+    c.httponly = try!(flag(obj, "httpOnly", index));
+    c.same_site = match try!(string(obj, "sameSite", index)) {
+        Some(s) => match from_str(s.as_slice()) {
+            Some(v) => Some(v),
+            None => return Err(invalid("sameSite", index)),
+        },
+        None => None,
+    };
+
+    let field = match format {
+        BrowserFormat::Playwright | BrowserFormat::Har => "expires",
+        BrowserFormat::WebDriver => "expiry",
+    };
+    c.expires = match (format, obj.find(field)) {
+        (_, None) | (_, Some(&Json::Null)) => None,
+        (BrowserFormat::Har, Some(v)) => {
+            match v.as_string().and_then(|s| date::parse_iso8601(s)) {
+                Some(t) => Some(t),
+                None => return Err(invalid(field, index)),
+            }
+        }
+        (_, Some(v)) => match v.as_f64() {
+            Some(secs) if secs < 0.0 => None,
+            // Casting a float which is out of range for `i64` is undefined,
+            // so anything after the year 9999 is rejected.
+            Some(secs) if secs < MAX_EXPIRY => {
+                Some(time::at_utc(Timespec::new(secs as i64, 0)))
+            }
+            _ => return Err(invalid(field, index)),
+        },
+    };
+    return Ok(c);
+
+    fn invalid(field: &'static str, index: uint) -> BrowserJsonError {
+        BrowserJsonError::InvalidField { index: index, field: field }
+    }
+
+    fn string(obj: &Json, field: &'static str, index: uint)
+              -> Result<Option<String>, BrowserJsonError> {
+        match obj.find(field) {
+            None | Some(&Json::Null) => Ok(None),
+            Some(v) => match v.as_string() {
+                Some(s) => Ok(Some(s.to_string())),
+                None => Err(invalid(field, index)),
+            },
+        }
+    }
+
+    fn flag(obj: &Json, field: &'static str, index: uint)
+            -> Result<bool, BrowserJsonError> {
+        match obj.find(field) {
+            None | Some(&Json::Null) => Ok(false),
+            Some(v) => v.as_boolean().ok_or(invalid(field, index)),
+        }
+    }
+}
+
+fn cookie_to_json(c: &Cookie, format: BrowserFormat, now: Timespec) -> Json {
+    let mut obj = TreeMap::new();
+    // An encoded name can't contain `=`.
+    let pair = c.pair().to_string();
+    let eq = pair.as_slice().find('=').unwrap();
+    obj.insert("name".to_string(), Json::String(pair.as_slice().slice_to(eq).to_string()));
+    obj.insert("value".to_string(),
+               Json::String(pair.as_slice().slice_from(eq + 1).to_string()));
+    match c.domain {
+        Some(ref d) => { obj.insert("domain".to_string(), Json::String(d.clone())); }
+        None => {}
+    }
+    let path = c.path.clone().unwrap_or("/".to_string());
+    obj.insert("path".to_string(), Json::String(path));
+    obj.insert("secure".to_string(), Json::Boolean(c.secure));
+    obj.insert("httpOnly".to_string(), Json::Boolean(c.httponly));
+    match c.same_site {
+        Some(s) => { obj.insert("sameSite".to_string(), Json::String(s.to_string())); }
+        None => {}
+    }
+
+    // An expired cookie is clamped to the epoch rather than left at the
+    // distant past `Cookie::expiration` uses for it.
+    let expires = match c.expiration(now) {
+        Expiration::Session => None,
+        Expiration::At(t) => Some(if t.sec < 0 { Timespec::new(0, 0) } else { t }),
+    };
+    match (format, expires) {
+        (BrowserFormat::Playwright, None) => {
+            obj.insert("expires".to_string(), Json::I64(-1));
+        }
+        (BrowserFormat::Playwright, Some(t)) => {
+            obj.insert("expires".to_string(), Json::I64(t.sec));
+        }
+        (BrowserFormat::Har, Some(t)) => {
+            let t = date::format_iso8601(&time::at_utc(t));
+            obj.insert("expires".to_string(), Json::String(t));
+        }
+        (BrowserFormat::WebDriver, Some(t)) => {
+            obj.insert("expiry".to_string(), Json::I64(t.sec));
+        }
+        (BrowserFormat::Har, None) | (BrowserFormat::WebDriver, None) => {}
+    }
+    Json::Object(obj)
+}
+
+/// An error reading browser cookie JSON.
+#[deriving(PartialEq, Eq, Clone)]
+pub enum BrowserJsonError {
+    /// The input wasn't valid JSON.
+    Syntax(String),
+    /// The JSON didn't have the layout of the expected format.
+    UnexpectedShape,
+    /// A required field of the cookie at `index` was missing.
+    MissingField { index: uint, field: &'static str },
+    /// A field of the cookie at `index` had the wrong type or value.
+    InvalidField { index: uint, field: &'static str },
+}
+
+impl fmt::Show for BrowserJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.description()));
+        match *self {
+            BrowserJsonError::Syntax(ref s) => write!(f, ": {}", s),
+            BrowserJsonError::UnexpectedShape => Ok(()),
+            BrowserJsonError::MissingField { index, field } |
+            BrowserJsonError::InvalidField { index, field } => {
+                write!(f, " `{}` in cookie {}", field, index)
+            }
+        }
+    }
+}
+
+impl Error for BrowserJsonError {
+    fn description(&self) -> &str {
+        match *self {
+            BrowserJsonError::Syntax(..) => "invalid JSON",
+            BrowserJsonError::UnexpectedShape => "unexpected JSON layout",
+            BrowserJsonError::MissingField { .. } => "missing field",
+            BrowserJsonError::InvalidField { .. } => "invalid field",
+        }
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::json;
+    use time;
+    use time::Timespec;
+
+    use {Cookie, CookiesTxt, SameSite};
+    use super::{BrowserFormat, BrowserJsonError, from_browser_json, to_browser_json};
+
+    fn cookie() -> Cookie {
+        let mut c = Cookie::new("id".to_string(), "a b".to_string());
+        c.domain = Some(".example.com".to_string());
+        c.path = Some("/app".to_string());
+        c.secure = true;
+        c.httponly = true;
+        c.same_site = Some(SameSite::Strict);
+        c.expires = Some(time::at_utc(Timespec::new(2000000000, 0)));
+        c
+    }
+
+    #[test]
+    fn playwright() {
+        let s = "{\"cookies\":[{\"name\":\"id\",\"value\":\"a b\",\
+                 \"domain\":\".example.com\",\"path\":\"/app\",\
+                 \"expires\":2000000000.25,\"httpOnly\":true,\"secure\":true,\
+                 \"sameSite\":\"Strict\"},\
+                 {\"name\":\"s\",\"value\":\"\",\"domain\":\"example.com\",\
+                 \"path\":\"/\",\"expires\":-1,\"httpOnly\":false,\
+                 \"secure\":false,\"sameSite\":\"Lax\"}],\"origins\":[]}";
+        let cookies = from_browser_json(s, BrowserFormat::Playwright).unwrap();
+        assert_eq!(cookies[0], cookie());
+        assert_eq!(cookies[1].expires, None);
+        assert_eq!(cookies[1].same_site, Some(SameSite::Lax));
+
+        let out = to_browser_json(cookies.as_slice(), BrowserFormat::Playwright);
+        let json = json::from_str(out.as_slice()).unwrap();
+        assert_eq!(json.find("origins").unwrap().as_array().unwrap().len(), 0);
+        let first = &json.find("cookies").unwrap().as_array().unwrap()[0];
+        assert_eq!(first.find("expires").unwrap().as_i64(), Some(2000000000));
+        assert_eq!(first.find("httpOnly").unwrap().as_boolean(), Some(true));
+        assert_eq!(from_browser_json(out.as_slice(), BrowserFormat::Playwright),
+                   Ok(cookies));
+    }
+
+    #[test]
+    fn har() {
+        let s = "{\"log\":{\"entries\":[{\
+                 \"request\":{\"cookies\":[{\"name\":\"a\",\"value\":\"1\"}]},\
+                 \"response\":{\"cookies\":[{\"name\":\"id\",\"value\":\"a b\",\
+                 \"domain\":\".example.com\",\"path\":\"/app\",\
+                 \"expires\":\"2033-05-18T03:33:20.000Z\",\"httpOnly\":true,\
+                 \"secure\":true,\"sameSite\":\"Strict\",\"comment\":\"\"}]}}]}}";
+        let cookies = from_browser_json(s, BrowserFormat::Har).unwrap();
+        assert_eq!(cookies.len(), 2);
+        assert_eq!(cookies[0].name.as_slice(), "a");
+        assert_eq!(cookies[1], cookie());
+
+        let out = to_browser_json(&[cookie()], BrowserFormat::Har);
+        let json = json::from_str(out.as_slice()).unwrap();
+        assert_eq!(json.as_array().unwrap()[0].find("expires").unwrap().as_string(),
+                   Some("2033-05-18T03:33:20Z"));
+        assert_eq!(from_browser_json(out.as_slice(), BrowserFormat::Har),
+                   Ok(vec![cookie()]));
+    }
+
+    #[test]
+    fn webdriver() {
+        let s = "{\"value\":[{\"name\":\"id\",\"value\":\"a b\",\
+                 \"domain\":\".example.com\",\"path\":\"/app\",\
+                 \"expiry\":2000000000,\"httpOnly\":true,\"secure\":true,\
+                 \"sameSite\":\"Strict\"}]}";
+        let cookies = from_browser_json(s, BrowserFormat::WebDriver).unwrap();
+        assert_eq!(cookies, vec![cookie()]);
+
+        let mut session = cookie();
+        session.expires = None;
+        let out = to_browser_json(&[session.clone()], BrowserFormat::WebDriver);
+        assert!(!out.as_slice().contains("expiry"));
+        assert_eq!(from_browser_json(out.as_slice(), BrowserFormat::WebDriver),
+                   Ok(vec![session]));
+
+        let mut quoted = cookie();
+        quoted.quoted = true;
+        let out = to_browser_json(&[quoted.clone()], BrowserFormat::WebDriver);
+        assert!(out.as_slice().contains("\"value\":\"\\\"a%20b\\\"\""));
+        assert_eq!(from_browser_json(out.as_slice(), BrowserFormat::WebDriver),
+                   Ok(vec![quoted]));
+    }
+
+    #[test]
+    fn same_as_cookies_txt() {
+        let txt = "example.com\tFALSE\t/app\tTRUE\t2000000000\tid%3D\t50%25%20off\n";
+        let s = "[{\"name\":\"id%3D\",\"value\":\"50%25%20off\",\
+                 \"domain\":\"example.com\",\"path\":\"/app\",\
+                 \"expires\":2000000000,\"secure\":true}]";
+        let cookies = from_browser_json(s, BrowserFormat::Playwright).unwrap();
+        assert_eq!(cookies, CookiesTxt::parse(txt).unwrap().cookies());
+        assert_eq!(cookies[0].name.as_slice(), "id=");
+        assert_eq!(cookies[0].value.as_slice(), "50% off");
+
+        let out = to_browser_json(cookies.as_slice(), BrowserFormat::Playwright);
+        assert!(out.as_slice().contains("\"value\":\"50%25%20off\""));
+        assert_eq!(from_browser_json(out.as_slice(), BrowserFormat::Playwright),
+                   Ok(cookies));
+    }
+
+    #[test]
+    fn expired() {
+        let mut c = Cookie::new("gone".to_string(), "".to_string());
+        c.max_age = Some(0);
+        let out = to_browser_json(&[c], BrowserFormat::WebDriver);
+        let json = json::from_str(out.as_slice()).unwrap();
+        assert_eq!(json.as_array().unwrap()[0].find("expiry").unwrap().as_i64(),
+                   Some(0));
+    }
+
+    #[test]
+    fn errors() {
+        let f = BrowserFormat::Playwright;
+        match from_browser_json("{", f) {
+            Err(BrowserJsonError::Syntax(..)) => {}
+            other => panic!("unexpected result: {}", other),
+        }
+        assert_eq!(from_browser_json("{}", f), Err(BrowserJsonError::UnexpectedShape));
+        assert_eq!(from_browser_json("[{\"value\":\"1\"}]", f),
+                   Err(BrowserJsonError::MissingField { index: 0, field: "name" }));
+        assert_eq!(from_browser_json("[{\"name\":\"a\",\"value\":\"1\"},\
+                                      {\"name\":\"b\",\"value\":\"1\",\"secure\":1}]", f),
+                   Err(BrowserJsonError::InvalidField { index: 1, field: "secure" }));
+        assert_eq!(from_browser_json("[{\"name\":\"a\",\"value\":\"1\",\
+                                      \"expires\":\"soon\"}]", f),
+                   Err(BrowserJsonError::InvalidField { index: 0, field: "expires" }));
+        assert_eq!(from_browser_json("[{\"name\":\"a\",\"value\":\"1\",\
+                                      \"expiry\":1e300}]", BrowserFormat::WebDriver),
+                   Err(BrowserJsonError::InvalidField { index: 0, field: "expiry" }));
+        let err = BrowserJsonError::MissingField { index: 2, field: "value" };
+        assert_eq!(err.to_string().as_slice(), "missing field `value` in cookie 2");
+    }
+}
@@ -0,0 +1,355 @@
+//! A builder for constructing and validating cookies.
+
+use std::collections::TreeMap;
+use std::default::Default;
+use time;
+
+use {Cookie, ParseError, ValidationError, SameSite, Priority};
+
+/// The largest number of bytes browsers accept in a cookie's name and value.
+pub const MAX_NAME_VALUE_LEN: uint = 4096;
+
+/// The largest number of bytes browsers accept in an attribute's value.
+pub const MAX_ATTR_VALUE_LEN: uint = 1024;
+
+/// The limits a cookie must stay within for browsers to accept it.
+///
+/// The defaults are those of RFC 6265bis, `MAX_NAME_VALUE_LEN` and
+/// `MAX_ATTR_VALUE_LEN`. A browser silently ignores a `Set-Cookie` header
+/// which exceeds them.
+#[deriving(PartialEq, Eq, Clone, Copy, Show)]
+pub struct Limits {
+    /// The most bytes the name and value may take up together.
+    pub max_name_value_len: uint,
+    /// The most bytes any attribute value may take up.
+    pub max_attr_value_len: uint,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_name_value_len: MAX_NAME_VALUE_LEN,
+            max_attr_value_len: MAX_ATTR_VALUE_LEN,
+        }
+    }
+}
+
+impl Limits {
+    /// Checks that `c` is within these limits once it is written into a
+    /// `Set-Cookie` header, where percent-encoding can make the name, value
+    /// and attribute values larger.
+    pub fn check(&self, c: &Cookie) -> Result<(), ValidationError> {
+        self.check_header(c.to_string().as_slice())
+    }
+
+    /// Checks that the `Set-Cookie` header value `s` is within these limits
+    /// and contains no control characters other than tab, measuring each
+    /// part as a browser would: without the surrounding whitespace, and
+    /// without decoding anything.
+    pub fn check_header(&self, s: &str) -> Result<(), ValidationError> {
+        let mut pieces = s.split(';');
+        let pair = pieces.next().unwrap_or("");
+        let (name, value) = split(pair);
+        match name.chars().find(|ch| is_ctl(*ch)) {
+            Some(ch) => return Err(ValidationError::InvalidNameChar(ch)),
+            None => {}
+        }
+        match value.chars().find(|ch| is_ctl(*ch)) {
+            Some(ch) => return Err(ValidationError::InvalidValueChar(ch)),
+            None => {}
+        }
+        if name.len() + value.len() > self.max_name_value_len {
+            return Err(ValidationError::TooLarge(name.len() + value.len()))
+        }
+
+        for piece in pieces {
+            let (k, v) = split(piece);
+            if v.len() > self.max_attr_value_len {
+                return Err(ValidationError::AttrTooLarge(k.to_string()))
+            }
+            if piece.chars().any(|ch| is_ctl(ch)) {
+                return Err(ValidationError::InvalidAttr(k.to_string()))
+            }
+        }
+        return Ok(());
+
+        fn split(piece: &str) -> (&str, &str) {
+            match piece.find('=') {
+                Some(i) => (piece.slice_to(i).trim(), piece.slice_from(i + 1).trim()),
+                None => (piece.trim(), ""),
+            }
+        }
+
+        // RFC 6265bis has user agents ignore a cookie containing one of these.
+        fn is_ctl(ch: char) -> bool {
+            ch.is_control() && ch != '\t'
+        }
+    }
+}
+
+/// A builder for a `Cookie`, checking it for problems when it is finished.
+///
+/// Unlike `Cookie::new`, no path is set unless one is given.
+///
+/// # Example
+///
+/// ```
+/// use cookie::{Cookie, SameSite};
+///
+/// let c = Cookie::build("session", "abc123")
+///                .path("/")
+///                .secure(true)
+///                .http_only(true)
+///                .same_site(SameSite::Lax)
+///                .max_age(3600)
+///                .finish()
+///                .unwrap();
+/// assert_eq!(c.to_string().as_slice(),
+///            "session=abc123; HttpOnly; Secure; Path=/; Max-Age=3600; \
+///             SameSite=Lax");
+/// ```
+pub struct CookieBuilder {
+    cookie: Cookie,
+    limits: Limits,
+}
+
+impl CookieBuilder {
+    /// Creates a builder for a cookie with the given name and value.
+    pub fn new(name: &str, value: &str) -> CookieBuilder {
+        let mut cookie = Cookie::new(name.to_string(), value.to_string());
+        cookie.path = None;
+        CookieBuilder { cookie: cookie, limits: Default::default() }
+    }
+
+    /// Sets whether the value is written in double quotes.
+    pub fn quoted(mut self, quoted: bool) -> CookieBuilder {
+        self.cookie.quoted = quoted;
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> CookieBuilder {
+        self.cookie.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> CookieBuilder {
+        self.cookie.path = Some(path.to_string());
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> CookieBuilder {
+        self.cookie.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> CookieBuilder {
+        self.cookie.httponly = http_only;
+        self
+    }
+
+    pub fn max_age(mut self, seconds: i64) -> CookieBuilder {
+        self.cookie.max_age = Some(seconds);
+        self
+    }
+
+    pub fn expires(mut self, expires: time::Tm) -> CookieBuilder {
+        self.cookie.expires = Some(expires);
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> CookieBuilder {
+        self.cookie.same_site = Some(same_site);
+        self
+    }
+
+    pub fn partitioned(mut self, partitioned: bool) -> CookieBuilder {
+        self.cookie.partitioned = partitioned;
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> CookieBuilder {
+        self.cookie.priority = Some(priority);
+        self
+    }
+
+    /// Adds a custom attribute, replacing any previous one of the same name.
+    pub fn custom(mut self, name: &str, value: &str) -> CookieBuilder {
+        self.cookie.custom.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Sets the limits the cookie is checked against, instead of the
+    /// defaults.
+    pub fn limits(mut self, limits: Limits) -> CookieBuilder {
+        self.limits = limits;
+        self
+    }
+
+    /// Checks the cookie and returns it if it is valid.
+    ///
+    /// The name and the names of custom attributes must be RFC 2616 tokens,
+    /// the value and attribute values must not contain control characters,
+    /// and the cookie must be within the builder's `Limits`. Finally, the
+    /// cookie must pass `Cookie::validate`.
+    pub fn finish(self) -> Result<Cookie, ParseError> {
+        match check(&self.cookie, &self.limits) {
+            Ok(()) => Ok(self.cookie),
+            Err(e) => Err(ParseError::Invalid {
+                error: e,
+                input: self.cookie.to_string(),
+            }),
+        }
+    }
+}
+
+fn check(c: &Cookie, limits: &Limits) -> Result<(), ValidationError> {
+    if c.name.is_empty() {
+        return Err(ValidationError::EmptyName)
+    }
+    match c.name.chars().find(|ch| !is_token_char(*ch)) {
+        Some(ch) => return Err(ValidationError::InvalidNameChar(ch)),
+        None => {}
+    }
+    try!(check_controls(c));
+    try!(limits.check(c));
+    try!(check_custom(&c.custom));
+    c.validate()
+}
+
+// Control characters would be percent-encoded when the cookie is written, so
+// a browser would accept them, but they are almost certainly a mistake.
+fn check_controls(c: &Cookie) -> Result<(), ValidationError> {
+    match c.value.chars().find(|ch| ch.is_control()) {
+        Some(ch) => return Err(ValidationError::InvalidValueChar(ch)),
+        None => {}
+    }
+    let attrs = vec![("Domain", c.domain.as_ref()), ("Path", c.path.as_ref())];
+    for (attr, v) in attrs.into_iter() {
+        match v {
+            Some(v) if v.as_slice().chars().any(|ch| ch.is_control()) => {
+                return Err(ValidationError::InvalidAttr(attr.to_string()))
+            }
+            _ => {}
+        }
+    }
+    for (k, v) in c.custom.iter() {
+        if v.as_slice().chars().any(|ch| ch.is_control()) {
+            return Err(ValidationError::InvalidAttr(k.clone()))
+        }
+    }
+    Ok(())
+}
+
+fn check_custom(custom: &TreeMap<String, String>) -> Result<(), ValidationError> {
+    for k in custom.keys() {
+        if k.is_empty() || !k.chars().all(|ch| is_token_char(ch)) {
+            return Err(ValidationError::InvalidAttr(k.clone()))
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether `ch` may appear in an RFC 2616 token.
+pub fn is_token_char(ch: char) -> bool {
+    match ch {
+        '(' | ')' | '<' | '>' | '@' | ',' | ';' | ':' | '\\' | '"' |
+        '/' | '[' | ']' | '?' | '=' | '{' | '}' | ' ' | '\t' => false,
+        ch => ch > '\x1f' && ch < '\x7f',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Cookie, ParseError, ValidationError, SameSite, Priority};
+    use super::{Limits, MAX_NAME_VALUE_LEN};
+
+    fn error(r: Result<Cookie, ParseError>) -> ValidationError {
+        match r {
+            Err(ParseError::Invalid { error, .. }) => error,
+            r => panic!("unexpected result: {}", r),
+        }
+    }
+
+    #[test]
+    fn build() {
+        let c = Cookie::build("foo", "bar")
+                       .domain("foo.com")
+                       .path("/foo")
+                       .secure(true)
+                       .http_only(true)
+                       .max_age(4)
+                       .same_site(SameSite::Strict)
+                       .partitioned(true)
+                       .priority(Priority::Low)
+                       .custom("wut", "lol")
+                       .finish()
+                       .unwrap();
+        let mut expected = Cookie::new("foo".to_string(), "bar".to_string());
+        expected.domain = Some("foo.com".to_string());
+        expected.path = Some("/foo".to_string());
+        expected.secure = true;
+        expected.httponly = true;
+        expected.max_age = Some(4);
+        expected.same_site = Some(SameSite::Strict);
+        expected.partitioned = true;
+        expected.priority = Some(Priority::Low);
+        expected.custom.insert("wut".to_string(), "lol".to_string());
+        assert_eq!(c, expected);
+
+        let c = Cookie::build("foo", "bar").finish().unwrap();
+        assert_eq!(c.path, None);
+
+        let c = Cookie::build("foo", "bar").quoted(true).finish().unwrap();
+        assert!(c.quoted);
+        assert_eq!(c.to_string().as_slice(), "foo=\"bar\"");
+    }
+
+    #[test]
+    fn invalid_characters() {
+        assert_eq!(error(Cookie::build("", "bar").finish()),
+                   ValidationError::EmptyName);
+        assert_eq!(error(Cookie::build("f;o", "bar").finish()),
+                   ValidationError::InvalidNameChar(';'));
+        assert_eq!(error(Cookie::build("foo", "b\r\nr").finish()),
+                   ValidationError::InvalidValueChar('\r'));
+        assert_eq!(error(Cookie::build("foo", "bar").path("/\n").finish()),
+                   ValidationError::InvalidAttr("Path".to_string()));
+        assert_eq!(error(Cookie::build("foo", "bar").custom("a b", "").finish()),
+                   ValidationError::InvalidAttr("a b".to_string()));
+        assert!(Cookie::build("foo", "b a/r;").finish().is_ok());
+    }
+
+    #[test]
+    fn sizes() {
+        let value = String::from_char(MAX_NAME_VALUE_LEN - 3, 'a');
+        assert!(Cookie::build("foo", value.as_slice()).finish().is_ok());
+        let value = String::from_char(MAX_NAME_VALUE_LEN - 2, 'a');
+        assert_eq!(error(Cookie::build("foo", value.as_slice()).finish()),
+                   ValidationError::TooLarge(MAX_NAME_VALUE_LEN + 1));
+
+        let path = String::from_char(1025, '/');
+        assert_eq!(error(Cookie::build("foo", "bar").path(path.as_slice()).finish()),
+                   ValidationError::AttrTooLarge("Path".to_string()));
+
+        let limits = Limits { max_name_value_len: 8, max_attr_value_len: 2 };
+        assert!(Cookie::build("foo", "bar").limits(limits).finish().is_ok());
+        assert_eq!(error(Cookie::build("foo", "bar").custom("x", "abc")
+                                                     .limits(limits).finish()),
+                   ValidationError::AttrTooLarge("x".to_string()));
+        assert_eq!(error(Cookie::build("foo", "barbaz").limits(limits).finish()),
+                   ValidationError::TooLarge(9));
+
+        // Sizes are measured after percent-encoding.
+        let value = String::from_char(2000, ' ');
+        assert_eq!(error(Cookie::build("foo", value.as_slice()).finish()),
+                   ValidationError::TooLarge(6003));
+    }
+
+    #[test]
+    fn consistency() {
+        assert_eq!(error(Cookie::build("foo", "bar").partitioned(true).finish()),
+                   ValidationError::PartitionedWithoutSecure);
+        let c = Cookie::build("__Host-foo", "bar").secure(true).finish().unwrap();
+        assert_eq!(c.validate_prefix(), Err(ValidationError::HostPrefixWithoutRootPath));
+    }
+}
@@ -0,0 +1,140 @@
+//! `Encodable` and `Decodable` implementations for `Cookie`.
+//!
+//! These are only compiled with the `serialize` feature. `Expires` is stored
+//! as seconds since the Unix epoch and `SameSite` and `Priority` by name, so
+//! the encoded form doesn't depend on the timezone of the machine which wrote
+//! it.
+
+use serialize::{Encodable, Decodable, Encoder, Decoder};
+use time;
+use time::Timespec;
+
+use Cookie;
+use date;
+
+impl<S: Encoder<E>, E> Encodable<S, E> for Cookie {
+    fn encode(&self, s: &mut S) -> Result<(), E> {
+        s.emit_struct("Cookie", 14, |s| {
+            try!(s.emit_struct_field("name", 0, |s| self.name.encode(s)));
+            try!(s.emit_struct_field("value", 1, |s| self.value.encode(s)));
+            try!(s.emit_struct_field("expires", 2, |s| {
+                self.expires.as_ref().map(|t| date::to_timespec(t).sec).encode(s)
+            }));
+            try!(s.emit_struct_field("max_age", 3, |s| self.max_age.encode(s)));
+            try!(s.emit_struct_field("domain", 4, |s| self.domain.encode(s)));
+            try!(s.emit_struct_field("path", 5, |s| self.path.encode(s)));
+            try!(s.emit_struct_field("secure", 6, |s| self.secure.encode(s)));
+            try!(s.emit_struct_field("httponly", 7, |s| self.httponly.encode(s)));
+            try!(s.emit_struct_field("same_site", 8, |s| {
+                self.same_site.map(|v| v.to_string()).encode(s)
+            }));
+            try!(s.emit_struct_field("partitioned", 9, |s| {
+                self.partitioned.encode(s)
+            }));
+            try!(s.emit_struct_field("priority", 10, |s| {
+                self.priority.map(|v| v.to_string()).encode(s)
+            }));
+            try!(s.emit_struct_field("custom", 11, |s| self.custom.encode(s)));
+            try!(s.emit_struct_field("legacy", 12, |s| self.legacy.encode(s)));
+            s.emit_struct_field("quoted", 13, |s| self.quoted.encode(s))
+        })
+    }
+}
+
+impl<D: Decoder<E>, E> Decodable<D, E> for Cookie {
+    fn decode(d: &mut D) -> Result<Cookie, E> {
+        d.read_struct("Cookie", 14, |d| {
+            let mut c = Cookie::new(
+                try!(d.read_struct_field("name", 0, |d| Decodable::decode(d))),
+                try!(d.read_struct_field("value", 1, |d| Decodable::decode(d))));
+            let expires: Option<i64> =
+                try!(d.read_struct_field("expires", 2, |d| Decodable::decode(d)));
+            c.expires = expires.map(|secs| time::at_utc(Timespec::new(secs, 0)));
+            c.max_age = try!(d.read_struct_field("max_age", 3,
+                                                 |d| Decodable::decode(d)));
+            c.domain = try!(d.read_struct_field("domain", 4,
+                                                |d| Decodable::decode(d)));
+            c.path = try!(d.read_struct_field("path", 5, |d| Decodable::decode(d)));
+            c.secure = try!(d.read_struct_field("secure", 6,
+                                                |d| Decodable::decode(d)));
+            c.httponly = try!(d.read_struct_field("httponly", 7,
+                                                  |d| Decodable::decode(d)));
+            let same_site: Option<String> =
+                try!(d.read_struct_field("same_site", 8, |d| Decodable::decode(d)));
+            c.same_site = match same_site {
+                Some(s) => match from_str(s.as_slice()) {
+                    Some(v) => Some(v),
+                    None => return Err(d.error("invalid SameSite value")),
+                },
+                None => None,
+            };
+            c.partitioned = try!(d.read_struct_field("partitioned", 9,
+                                                     |d| Decodable::decode(d)));
+            let priority: Option<String> =
+                try!(d.read_struct_field("priority", 10, |d| Decodable::decode(d)));
+            c.priority = match priority {
+                Some(s) => match from_str(s.as_slice()) {
+                    Some(v) => Some(v),
+                    None => return Err(d.error("invalid Priority value")),
+                },
+                None => None,
+            };
+            c.custom = try!(d.read_struct_field("custom", 11,
+                                                |d| Decodable::decode(d)));
+            c.legacy = try!(d.read_struct_field("legacy", 12,
+                                                |d| Decodable::decode(d)));
+            c.quoted = try!(d.read_struct_field("quoted", 13,
+                                                |d| Decodable::decode(d)));
+            Ok(c)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::json;
+    use time;
+    use time::Timespec;
+
+    use {Cookie, Legacy, SameSite, Priority};
+
+    #[test]
+    fn round_trip() {
+        let mut c = Cookie::new("foo".to_string(), "b;r".to_string());
+        c.quoted = true;
+        c.expires = Some(time::at_utc(Timespec::new(784111777, 0)));
+        c.max_age = Some(-1);
+        c.domain = Some("foo.com".to_string());
+        c.path = None;
+        c.secure = true;
+        c.httponly = true;
+        c.same_site = Some(SameSite::None);
+        c.partitioned = true;
+        c.priority = Some(Priority::High);
+        c.custom.insert("wut".to_string(), "lol".to_string());
+        c.legacy = Some(Legacy {
+            version: Some(1),
+            comment: Some("hi".to_string()),
+            comment_url: None,
+            discard: true,
+            port: Some(vec![80, 8080]),
+        });
+
+        let encoded = json::encode(&c);
+        assert_eq!(json::decode::<Cookie>(encoded.as_slice()).unwrap(), c);
+
+        let c = Cookie::new("foo".to_string(), "bar".to_string());
+        let encoded = json::encode(&c);
+        assert_eq!(json::decode::<Cookie>(encoded.as_slice()).unwrap(), c);
+    }
+
+    #[test]
+    fn invalid() {
+        let s = "{\"name\":\"a\",\"value\":\"b\",\"expires\":null,\"max_age\":null,\
+                 \"domain\":null,\"path\":null,\"secure\":false,\
+                 \"httponly\":false,\"same_site\":\"Sideways\",\
+                 \"partitioned\":false,\"priority\":null,\"custom\":{},\
+                 \"legacy\":null,\"quoted\":false}";
+        assert!(json::decode::<Cookie>(s).is_err());
+    }
+}
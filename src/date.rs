@@ -0,0 +1,374 @@
+//! Parsing and formatting of the dates in the `Expires` attribute.
+//!
+//! Servers send `Expires` dates in a variety of formats: the RFC 1123 form,
+//! the RFC 850 form with dashes and a two-digit year, the `asctime` form, and
+//! any number of mangled variations of these. The cookie-date algorithm of
+//! RFC 6265 section 5.1.1, implemented here, is the one browsers use to pick
+//! the time, day, month and year out of such a string.
+//!
+//! Dates are always written in the IMF-fixdate format, in GMT.
+
+use time;
+use time::{Tm, Timespec};
+
+static MONTHS: &'static [&'static str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun",
+    "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+static MONTH_NAMES: &'static [&'static str] = &[
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+    "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+static DAY_NAMES: &'static [&'static str] = &[
+    "Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat",
+];
+
+/// Parses a date as it appears in the `Expires` attribute of a cookie.
+///
+/// Returns `None` if no time, day of month, month or year could be found, or
+/// if they don't form a valid date no earlier than the year 1601. The
+/// returned time is in UTC.
+///
+/// # Example
+///
+/// ```
+/// use cookie::parse_cookie_date;
+///
+/// let a = parse_cookie_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+/// let b = parse_cookie_date("Sunday, 06-Nov-94 08:49:37 GMT").unwrap();
+/// let c = parse_cookie_date("Sun Nov  6 08:49:37 1994").unwrap();
+/// assert!(a == b && b == c);
+/// ```
+pub fn parse_cookie_date(s: &str) -> Option<Tm> {
+    let mut time = None;
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+
+    // Step 1: tokenize, then step 2: match each token against the first
+    // production which hasn't been found yet, in order.
+    let tokens = s.as_bytes().split(|b| is_delimiter(*b)).filter(|t| !t.is_empty());
+    for token in tokens {
+        if time.is_none() {
+            match parse_time(token) {
+                Some(t) => { time = Some(t); continue }
+                None => {}
+            }
+        }
+        if day.is_none() {
+            match digits(token, 1, 2) {
+                Some((d, _)) => { day = Some(d); continue }
+                None => {}
+            }
+        }
+        if month.is_none() {
+            match parse_month(token) {
+                Some(m) => { month = Some(m); continue }
+                None => {}
+            }
+        }
+        if year.is_none() {
+            match digits(token, 2, 4) {
+                Some((y, _)) => { year = Some(y); continue }
+                None => {}
+            }
+        }
+    }
+
+    let ((hour, minute, second), day, month, year) = match (time, day, month, year) {
+        (Some(t), Some(d), Some(m), Some(y)) => (t, d, m, y),
+        _ => return None,
+    };
+
+    // Steps 3 and 4: map two-digit years onto 1970-2069.
+    let year = match year {
+        70...99 => year + 1900,
+        0...69 => year + 2000,
+        y => y,
+    };
+
+    // Steps 5 and 6: every field must be in range and the date must exist.
+    if year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None
+    }
+    if day < 1 || day > days_in_month(year, month) {
+        return None
+    }
+
+    let days = days_from_civil(year as i64, month as i64, day as i64);
+    let secs = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    Some(time::at_utc(Timespec::new(secs, 0)))
+}
+
+/// Formats a time as an IMF-fixdate, such as `Sun, 06 Nov 1994 08:49:37 GMT`,
+/// as RFC 6265 requires for the `Expires` attribute.
+///
+/// The time is converted to GMT first, whatever its UTC offset.
+pub fn format_cookie_date(tm: &Tm) -> String {
+    let t = time::at_utc(to_timespec(tm));
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            DAY_NAMES[t.tm_wday as uint], t.tm_mday,
+            MONTH_NAMES[t.tm_mon as uint], t.tm_year + 1900,
+            t.tm_hour, t.tm_min, t.tm_sec)
+}
+
+/// Converts a broken-down time to an instant using its own UTC offset.
+///
+/// `Tm::to_timespec` uses the local timezone for any time which isn't in UTC,
+/// whatever offset the time itself records.
+pub fn to_timespec(tm: &Tm) -> Timespec {
+    let days = days_from_civil(tm.tm_year as i64 + 1900, tm.tm_mon as i64 + 1,
+                               tm.tm_mday as i64);
+    let secs = days * 86400 + tm.tm_hour as i64 * 3600 + tm.tm_min as i64 * 60 +
+               tm.tm_sec as i64 - tm.tm_utcoff as i64;
+    Timespec::new(secs, tm.tm_nsec)
+}
+
+/// Parses an ISO 8601 date-time such as `2009-07-24T19:20:30.45+01:00`, as
+/// used in HAR files. Fractional seconds are dropped and the offset may be
+/// `Z` or `[+-]HH:MM`. The returned time is in UTC.
+pub fn parse_iso8601(s: &str) -> Option<Tm> {
+    let s = s.as_bytes();
+    let mut pos = 0;
+    let mut fields = Vec::new();
+    for &(len, sep) in [(4, b'-'), (2, b'-'), (2, b'T'), (2, b':'),
+                        (2, b':'), (2, 0)].iter() {
+        let (n, _) = match digits(s.slice_from(pos), len, len) {
+            Some(p) => p, None => return None,
+        };
+        fields.push(n);
+        pos += len;
+        if sep != 0 {
+            if s.get(pos) != Some(&sep) { return None }
+            pos += 1;
+        }
+    }
+    let (year, month, day) = (fields[0], fields[1], fields[2]);
+    let (hour, minute, second) = (fields[3], fields[4], fields[5]);
+
+    if s.get(pos) == Some(&b'.') {
+        pos += 1;
+        let n = s.slice_from(pos).iter().take_while(|b| **b >= b'0' && **b <= b'9')
+                 .count();
+        if n == 0 { return None }
+        pos += n;
+    }
+    let offset = match s.slice_from(pos) {
+        rest if rest == b"Z" || rest == b"z" => 0,
+        rest if rest.len() == 6 && rest[3] == b':' => {
+            let sign = match rest[0] { b'+' => 1, b'-' => -1, _ => return None };
+            let h = match digits(rest.slice(1, 3), 2, 2) {
+                Some((h, _)) => h, None => return None,
+            };
+            let m = match digits(rest.slice_from(4), 2, 2) {
+                Some((m, _)) => m, None => return None,
+            };
+            sign * (h * 3600 + m * 60) as i64
+        }
+        _ => return None,
+    };
+
+    if month < 1 || month > 12 || hour > 23 || minute > 59 || second > 59 {
+        return None
+    }
+    if day < 1 || day > days_in_month(year, month) {
+        return None
+    }
+    let days = days_from_civil(year as i64, month as i64, day as i64);
+    let secs = days * 86400 + (hour * 3600 + minute * 60 + second) as i64 - offset;
+    Some(time::at_utc(Timespec::new(secs, 0)))
+}
+
+/// Formats a time in UTC as an ISO 8601 date-time, such as
+/// `1994-11-06T08:49:37Z`.
+pub fn format_iso8601(tm: &Tm) -> String {
+    let t = time::at_utc(to_timespec(tm));
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", t.tm_year + 1900,
+            t.tm_mon + 1, t.tm_mday, t.tm_hour, t.tm_min, t.tm_sec)
+}
+
+fn is_delimiter(b: u8) -> bool {
+    match b {
+        0x09 | 0x20...0x2F | 0x3B...0x40 | 0x5B...0x60 | 0x7B...0x7E => true,
+        _ => false,
+    }
+}
+
+/// Matches `min` to `max` leading digits which are not followed by another
+/// digit, returning their value and how many there were.
+fn digits(s: &[u8], min: uint, max: uint) -> Option<(u32, uint)> {
+    let n = s.iter().take_while(|b| **b >= b'0' && **b <= b'9').count();
+    if n < min || n > max {
+        return None
+    }
+    let value = s.slice_to(n).iter().fold(0, |acc, b| acc * 10 + (*b - b'0') as u32);
+    Some((value, n))
+}
+
+/// Matches the `time` production, `1*2DIGIT ":" 1*2DIGIT ":" 1*2DIGIT`
+/// followed by anything other than a digit.
+fn parse_time(token: &[u8]) -> Option<(u32, u32, u32)> {
+    let (hour, n) = match digits(token, 1, 2) { Some(p) => p, None => return None };
+    let rest = token.slice_from(n);
+    if rest.get(0) != Some(&b':') { return None }
+    let rest = rest.slice_from(1);
+    let (minute, n) = match digits(rest, 1, 2) { Some(p) => p, None => return None };
+    let rest = rest.slice_from(n);
+    if rest.get(0) != Some(&b':') { return None }
+    let rest = rest.slice_from(1);
+    let (second, _) = match digits(rest, 1, 2) { Some(p) => p, None => return None };
+    Some((hour, minute, second))
+}
+
+/// Matches a token starting with the first three letters of a month, in any
+/// case, returning the month number starting at 1.
+fn parse_month(token: &[u8]) -> Option<u32> {
+    if token.len() < 3 {
+        return None
+    }
+    let prefix = token.slice_to(3).iter().map(|b| {
+        if *b >= b'A' && *b <= b'Z' { *b - b'A' + b'a' } else { *b }
+    }).collect::<Vec<u8>>();
+    MONTHS.iter().position(|m| m.as_bytes() == prefix.as_slice())
+          .map(|i| i as u32 + 1)
+}
+
+fn is_leap_year(year: u32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the number of days between the Unix epoch and the given date in
+/// the proleptic Gregorian calendar, with `month` starting at 1.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 +
+                     day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Tm, Timespec};
+    use super::{parse_cookie_date, format_cookie_date, to_timespec};
+    use super::{parse_iso8601, format_iso8601};
+
+    // Sun, 06 Nov 1994 08:49:37 GMT with the given local fields and offset.
+    fn local(mday: i32, hour: i32, min: i32, wday: i32, utcoff: i32) -> Tm {
+        Tm {
+            tm_sec: 37, tm_min: min, tm_hour: hour, tm_mday: mday, tm_mon: 10,
+            tm_year: 94, tm_wday: wday, tm_yday: 303 + mday, tm_isdst: 0,
+            tm_utcoff: utcoff, tm_nsec: 0,
+        }
+    }
+
+    fn year(s: &str) -> Option<i32> {
+        fields(s).map(|(year, _, _, _, _, _)| year)
+    }
+
+    fn fields(s: &str) -> Option<(i32, i32, i32, i32, i32, i32)> {
+        parse_cookie_date(s).map(|t| {
+            (t.tm_year + 1900, t.tm_mon + 1, t.tm_mday,
+             t.tm_hour, t.tm_min, t.tm_sec)
+        })
+    }
+
+    #[test]
+    fn formats() {
+        let expected = Some((1994, 11, 6, 8, 49, 37));
+        assert_eq!(fields("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(fields("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(fields("Sun Nov  6 08:49:37 1994"), expected);
+        assert_eq!(fields("Sun, 06-Nov-1994 08:49:37 GMT"), expected);
+        assert_eq!(fields("06 november 1994 8:49:37"), expected);
+        assert_eq!(fields("SUN, 06 NOV 1994 08:49:37 UTC"), expected);
+    }
+
+    #[test]
+    fn weekday() {
+        let t = parse_cookie_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(t.tm_wday, 0);
+        assert_eq!(t.tm_yday, 309);
+    }
+
+    #[test]
+    fn two_digit_years() {
+        assert_eq!(year("01 Jan 70 00:00:00"), Some(1970));
+        assert_eq!(year("01 Jan 99 00:00:00"), Some(1999));
+        assert_eq!(year("01 Jan 00 00:00:00"), Some(2000));
+        assert_eq!(year("01 Jan 69 00:00:00"), Some(2069));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(fields(""), None);
+        assert_eq!(fields("Sun, 06 Nov 1994"), None);
+        assert_eq!(fields("Sun, 06 1994 08:49:37 GMT"), None);
+        assert_eq!(fields("Sun, 06 Nov 08:49:37 GMT"), None);
+        assert_eq!(fields("Sun, 06 Nov 1600 08:49:37 GMT"), None);
+        assert_eq!(fields("Sun, 06 Nov 1994 24:00:00 GMT"), None);
+        assert_eq!(fields("Sun, 06 Nov 1994 08:60:00 GMT"), None);
+        assert_eq!(fields("Sun, 32 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(fields("Sun, 00 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(fields("Fri, 30 Feb 2004 08:49:37 GMT"), None);
+        assert_eq!(fields("Sun, 06 Nov 19940 08:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn leap_day() {
+        assert_eq!(fields("29 Feb 2004 00:00:00"), Some((2004, 2, 29, 0, 0, 0)));
+        assert_eq!(fields("29 Feb 2100 00:00:00"), None);
+        assert_eq!(fields("29 Feb 2000 00:00:00"), Some((2000, 2, 29, 0, 0, 0)));
+    }
+
+    #[test]
+    fn format() {
+        let expected = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let utc = local(6, 8, 49, 0, 0);
+        assert_eq!(format_cookie_date(&utc).as_slice(), expected);
+
+        let zones = [local(6, 14, 19, 0, 5 * 3600 + 30 * 60),
+                     local(6, 0, 49, 0, -8 * 3600),
+                     local(5, 22, 49, 6, -10 * 3600),
+                     local(7, 1, 49, 1, 17 * 3600)];
+        for tm in zones.iter() {
+            assert_eq!(to_timespec(tm), Timespec::new(784111777, 0));
+            assert_eq!(format_cookie_date(tm).as_slice(), expected);
+        }
+
+        let parsed = parse_cookie_date("Fri, 01 Jan 2100 00:00:05 GMT").unwrap();
+        assert_eq!(format_cookie_date(&parsed).as_slice(),
+                   "Fri, 01 Jan 2100 00:00:05 GMT");
+    }
+
+    #[test]
+    fn iso8601() {
+        let expected = Some(Timespec::new(784111777, 0));
+        let parse = |s: &str| parse_iso8601(s).map(|t| to_timespec(&t));
+        assert_eq!(parse("1994-11-06T08:49:37Z"), expected);
+        assert_eq!(parse("1994-11-06T08:49:37.123Z"), expected);
+        assert_eq!(parse("1994-11-06T14:19:37+05:30"), expected);
+        assert_eq!(parse("1994-11-05T22:49:37.5-10:00"), expected);
+        assert_eq!(parse("1994-11-06 08:49:37Z"), None);
+        assert_eq!(parse("1994-11-06T08:49:37"), None);
+        assert_eq!(parse("1994-11-06T08:49:37.Z"), None);
+        assert_eq!(parse("1994-13-06T08:49:37Z"), None);
+        assert_eq!(parse("1994-11-31T08:49:37Z"), None);
+
+        let tm = local(6, 14, 19, 0, 5 * 3600 + 30 * 60);
+        assert_eq!(format_iso8601(&tm).as_slice(), "1994-11-06T08:49:37Z");
+    }
+}
@@ -0,0 +1,249 @@
+//! Rendering cookies into `Cookie` and `Set-Cookie` headers.
+//!
+//! A request carries all of its cookies in one `Cookie` header as `; `-joined
+//! `name=value` pairs, while a response sends one `Set-Cookie` header per
+//! cookie with its attributes. Both check each field against the grammar of
+//! RFC 6265 section 4.1, so that nothing in a cookie can end the header early
+//! or add pairs or attributes which weren't intended.
+
+use {Cookie, ValidationError};
+use builder::is_token_char;
+
+/// The value of a request `Cookie` header.
+///
+/// # Example
+///
+/// ```
+/// use cookie::{Cookie, CookieHeader};
+///
+/// let cookies = [Cookie::new("a".to_string(), "1".to_string()),
+///                Cookie::new("b".to_string(), "2".to_string())];
+/// let header = CookieHeader::new(&cookies).render().unwrap();
+/// assert_eq!(header.as_slice(), "a=1; b=2");
+/// ```
+pub struct CookieHeader<'a> {
+    cookies: &'a [Cookie],
+}
+
+impl<'a> CookieHeader<'a> {
+    pub fn new(cookies: &'a [Cookie]) -> CookieHeader<'a> {
+        CookieHeader { cookies: cookies }
+    }
+
+    /// Renders the cookies' name/value pairs, ignoring their attributes.
+    ///
+    /// Fails if a name is empty or isn't a token.
+    pub fn render(&self) -> Result<String, ValidationError> {
+        let mut ret = String::new();
+        for (i, cookie) in self.cookies.iter().enumerate() {
+            try!(check_name(cookie.name.as_slice()));
+            if i > 0 { ret.push_str("; "); }
+            ret.push_str(cookie.pair().to_string().as_slice());
+        }
+        Ok(ret)
+    }
+}
+
+/// The values of the `Set-Cookie` headers of a response.
+///
+/// # Example
+///
+/// ```
+/// use cookie::{Cookie, SetCookieHeader};
+///
+/// let mut c = Cookie::new("a".to_string(), "1".to_string());
+/// c.secure = true;
+/// let cookies = [c, Cookie::new("b".to_string(), "2".to_string())];
+/// let headers = SetCookieHeader::new(&cookies).render().unwrap();
+/// assert_eq!(headers, vec!["a=1; Secure; Path=/".to_string(),
+///                          "b=2; Path=/".to_string()]);
+/// ```
+pub struct SetCookieHeader<'a> {
+    cookies: &'a [Cookie],
+}
+
+impl<'a> SetCookieHeader<'a> {
+    pub fn new(cookies: &'a [Cookie]) -> SetCookieHeader<'a> {
+        SetCookieHeader { cookies: cookies }
+    }
+
+    /// Renders each cookie into the value of its own `Set-Cookie` header.
+    ///
+    /// See `Cookie::render` for the checks made on each cookie.
+    pub fn render(&self) -> Result<Vec<String>, ValidationError> {
+        let mut ret = Vec::new();
+        for cookie in self.cookies.iter() {
+            ret.push(try!(cookie.render()));
+        }
+        Ok(ret)
+    }
+}
+
+// Values, and the legacy Comment and CommentURL attributes, are always
+// percent-encoded when written, so only the parts of a cookie which are
+// written verbatim need checking. The allowed characters are
+// those of the grammar in RFC 6265 section 4.1.
+
+/// Checks that the name, domain, path and custom attribute names of `c` can
+/// be written into a `Set-Cookie` header unchanged.
+pub fn check_set_cookie(c: &Cookie) -> Result<(), ValidationError> {
+    try!(check_name(c.name.as_slice()));
+    try!(check_attr("Domain", &c.domain, is_domain_char));
+    try!(check_attr("Path", &c.path, is_path_char));
+    for k in c.custom.keys() {
+        if k.is_empty() || !k.as_slice().chars().all(|ch| is_token_char(ch)) {
+            return Err(ValidationError::InvalidAttr(k.clone()))
+        }
+    }
+    Ok(())
+}
+
+fn check_name(name: &str) -> Result<(), ValidationError> {
+    if name.is_empty() {
+        return Err(ValidationError::EmptyName)
+    }
+    match name.chars().find(|ch| !is_token_char(*ch)) {
+        Some(ch) => Err(ValidationError::InvalidNameChar(ch)),
+        None => Ok(()),
+    }
+}
+
+fn check_attr(attr: &str, value: &Option<String>, allowed: fn(char) -> bool)
+              -> Result<(), ValidationError> {
+    match *value {
+        Some(ref v) if !v.as_slice().chars().all(|ch| allowed(ch)) => {
+            Err(ValidationError::InvalidAttr(attr.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns whether `ch` may appear in a `Domain` attribute: letters, digits,
+/// hyphens and dots.
+pub fn is_domain_char(ch: char) -> bool {
+    match ch {
+        'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '.' => true,
+        _ => false,
+    }
+}
+
+/// Returns whether `ch` may appear in a `Path` attribute: any ASCII
+/// character other than a control character or `;`.
+pub fn is_path_char(ch: char) -> bool {
+    ch >= ' ' && ch < '\x7f' && ch != ';'
+}
+
+/// Percent-encodes the UTF-8 bytes of every character of `s` which isn't
+/// `allowed`.
+pub fn escape(s: &str, allowed: fn(char) -> bool) -> String {
+    let mut ret = String::new();
+    for ch in s.chars() {
+        if allowed(ch) {
+            ret.push(ch);
+        } else {
+            for b in ch.to_string().as_bytes().iter() {
+                ret.push_str(format!("%{:02X}", *b).as_slice());
+            }
+        }
+    }
+    ret
+}
+
+/// Writes `s` as a quoted-string, escaping `"` and `\` with a backslash.
+/// Control characters can't appear even in a quoted-string, and RFC 6265
+/// user agents split attributes on `;` even inside one, so these are
+/// percent-encoded instead, along with `%` itself.
+pub fn quote(s: &str) -> String {
+    let mut ret = String::from_char(1, '"');
+    for ch in s.chars() {
+        match ch {
+            '"' | '\\' => { ret.push('\\'); ret.push(ch); }
+            '\x00'...'\x1f' | '\x7f' | ';' | '%' => {
+                ret.push_str(format!("%{:02X}", ch as u32).as_slice());
+            }
+            ch => ret.push(ch),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use {Cookie, CookieHeader, SetCookieHeader, ValidationError};
+
+    fn cookie(name: &str, value: &str) -> Cookie {
+        Cookie::new(name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn request() {
+        assert_eq!(CookieHeader::new(&[]).render(), Ok(String::new()));
+
+        let mut a = cookie("a", "x; b=evil");
+        a.path = Some("/\r\nignored".to_string());
+        let cookies = [a, cookie("c", "\r\n")];
+        assert_eq!(CookieHeader::new(&cookies).render(),
+                   Ok("a=x%3B%20b=evil; c=%0D%0A".to_string()));
+
+        let cookies = [cookie("a", "1"), cookie("b;c", "2")];
+        assert_eq!(CookieHeader::new(&cookies).render(),
+                   Err(ValidationError::InvalidNameChar(';')));
+        let cookies = [cookie("a\r\nX-Evil: 1", "2")];
+        assert_eq!(CookieHeader::new(&cookies).render(),
+                   Err(ValidationError::InvalidNameChar('\r')));
+    }
+
+    #[test]
+    fn response() {
+        let mut c = cookie("a", "1; Domain=evil.example");
+        c.custom.insert("x".to_string(), "2;3".to_string());
+        assert_eq!(SetCookieHeader::new(&[c]).render(),
+                   Ok(vec!["a=1%3B%20Domain=evil.example; Path=/; x=2%3B3"
+                           .to_string()]));
+
+        let mut c = cookie("a", "1");
+        c.path = Some("/; Domain=evil.example".to_string());
+        assert_eq!(SetCookieHeader::new(&[c]).render(),
+                   Err(ValidationError::InvalidAttr("Path".to_string())));
+
+        let mut c = cookie("a", "1");
+        c.domain = Some("foo.com\r\nX-Evil: 1".to_string());
+        assert_eq!(SetCookieHeader::new(&[c]).render(),
+                   Err(ValidationError::InvalidAttr("Domain".to_string())));
+
+        let mut c = cookie("a", "1");
+        c.custom.insert("x=y".to_string(), "".to_string());
+        assert_eq!(SetCookieHeader::new(&[c]).render(),
+                   Err(ValidationError::InvalidAttr("x=y".to_string())));
+    }
+
+    #[test]
+    fn rfc6265_grammar() {
+        let mut c = cookie("a", "1");
+        c.domain = Some("foo_bar.com".to_string());
+        assert_eq!(c.render(), Err(ValidationError::InvalidAttr("Domain".to_string())));
+        c.domain = Some(".Foo-1.com".to_string());
+        c.path = Some("/caf\u00e9".to_string());
+        assert_eq!(c.render(), Err(ValidationError::InvalidAttr("Path".to_string())));
+        c.path = Some("/a b,c\"d".to_string());
+        assert!(c.render().is_ok());
+
+        assert_eq!(cookie("a b", "1").render(),
+                   Err(ValidationError::InvalidNameChar(' ')));
+        assert_eq!(cookie("", "1").render(), Err(ValidationError::EmptyName));
+        assert_eq!(cookie("a", "\"x\", y\\z").render(),
+                   Ok("a=%22x%22%2C%20y%5Cz; Path=/".to_string()));
+    }
+
+    #[test]
+    fn show_escapes() {
+        let mut c = cookie("a;b", "1");
+        c.path = Some("/\r\nX-Evil: 1".to_string());
+        c.domain = Some("evil.com; Secure".to_string());
+        c.custom.insert("x y".to_string(), "z".to_string());
+        assert_eq!(c.to_string().as_slice(),
+                   "a%3Bb=1; Path=/%0D%0AX-Evil: 1; \
+                    Domain=evil.com%3B%20Secure; x%20y=z");
+    }
+}
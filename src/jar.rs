@@ -11,10 +11,13 @@
 //! cookies, etc. This functionality can also be chaned together.
 
 use std::collections::{HashMap, HashSet};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::default::Default;
 use time;
+use time::Timespec;
 
-use Cookie;
+use {Cookie, Limits, ValidationError};
+use parse::parse_cookie_headers;
 
 /// A jar of cookies for managing a session
 ///
@@ -63,14 +66,50 @@ struct Child<'a> {
     write: Write,
 }
 
-type Read = fn(&Root, Cookie) -> Option<Cookie>;
+// Along with the cookie, a read reports whether it should be issued again,
+// for example because it was signed with a retired key.
+type Read = fn(&Root, Cookie) -> Option<(Cookie, bool)>;
 type Write = fn(&Root, Cookie) -> Cookie;
 
 struct Root {
     map: RefCell<HashMap<String, Cookie>>,
     new_cookies: RefCell<HashSet<String>>,
-    removed_cookies: RefCell<HashSet<String>>,
-    key: Vec<u8>,
+    removed_cookies: RefCell<HashMap<String, Cookie>>,
+    // The primary key followed by any retired keys.
+    keys: Vec<secure::Keys>,
+    legacy_keys: Cell<bool>,
+    legacy_encryption: Cell<bool>,
+    prefix_policy: Cell<PrefixPolicy>,
+    limits: Cell<Option<Limits>>,
+    reissue_template: RefCell<Option<Cookie>>,
+}
+
+/// How a cookie jar treats added cookies whose names start with `__Secure-`
+/// or `__Host-` but which don't meet the requirements of the prefix.
+///
+/// See `Cookie::validate_prefix` for the requirements.
+#[deriving(PartialEq, Eq, Clone, Copy, Show)]
+pub enum PrefixPolicy {
+    /// Cookies are added unchanged. This is the default.
+    Ignore,
+    /// Cookies which don't meet the requirements are rejected.
+    Reject,
+    /// Cookies are changed to meet the requirements: `Secure` is set and, for
+    /// `__Host-` cookies, the domain is cleared and the path set to `/`.
+    Fix,
+}
+
+/// The state of a cookie jar, for restoring it elsewhere.
+///
+/// This holds the jar's cookies along with which of them were added or
+/// removed, so that a jar restored with `CookieJar::from_snapshot` produces
+/// the same `delta`. The jar's key and prefix policy are not included.
+#[cfg(feature = "serialize")]
+#[deriving(PartialEq, Clone, Show, Encodable, Decodable)]
+pub struct JarSnapshot {
+    pub cookies: Vec<Cookie>,
+    pub new_cookies: Vec<String>,
+    pub removed_cookies: Vec<Cookie>,
 }
 
 /// Iterator over the cookies in a cookie jar
@@ -82,27 +121,85 @@ pub struct Iter<'a> {
 impl<'a> CookieJar<'a> {
     /// Creates a new empty cookie jar with the given signing key.
     ///
-    /// The given key is used to sign cookies in the signed cookie jar.
+    /// Separate keys for signing and for encryption are derived from the
+    /// given key with HKDF, see `set_legacy_keys` for reading cookies written
+    /// before this was done.
     pub fn new(key: &[u8]) -> CookieJar<'static> {
-
-        let normalized_key = if key.len() >= secure::MIN_KEY_LEN {
-            key.to_vec()
-        } else {
-            // Using a SHA-256 hash to normalize key as Rails suggests.
-            // See https://github.com/rails/rails/blob/master/activesupport/lib/active_support/message_encryptor.rb
-            secure::prepare_key(key)
-        };
-
         CookieJar {
             flavor: Flavor::Root(Root {
                 map: RefCell::new(HashMap::new()),
                 new_cookies: RefCell::new(HashSet::new()),
-                removed_cookies: RefCell::new(HashSet::new()),
-                key: normalized_key,
+                removed_cookies: RefCell::new(HashMap::new()),
+                keys: vec![secure::Keys::new(normalize_key(key))],
+                legacy_keys: Cell::new(false),
+                legacy_encryption: Cell::new(false),
+                prefix_policy: Cell::new(PrefixPolicy::Ignore),
+                limits: Cell::new(None),
+                reissue_template: RefCell::new(None),
             })
         }
     }
 
+    /// Creates a new cookie jar holding the cookies sent in the given request
+    /// `Cookie` headers.
+    ///
+    /// Each cookie is added with `add_original`. If a name appears more than
+    /// once only the first cookie is kept, as user agents send the cookie with
+    /// the most specific path first. Pieces without a name are skipped.
+    pub fn from_headers(key: &[u8], headers: &[&str]) -> CookieJar<'static> {
+        let mut jar = CookieJar::new(key);
+        for (name, value) in parse_cookie_headers(headers).into_iter() {
+            if name.is_empty() || jar.root().map.borrow().contains_key(&name) {
+                continue
+            }
+            jar.add_original(Cookie::new(name, value));
+        }
+        jar
+    }
+
+    /// Creates a new cookie jar with the given signing key from a snapshot
+    /// taken by `snapshot`.
+    #[cfg(feature = "serialize")]
+    pub fn from_snapshot(key: &[u8], snapshot: JarSnapshot) -> CookieJar<'static> {
+        let jar = CookieJar::new(key);
+        {
+            let root = jar.root();
+            let mut map = root.map.borrow_mut();
+            for cookie in snapshot.cookies.into_iter() {
+                map.insert(cookie.name.clone(), cookie);
+            }
+            root.new_cookies.borrow_mut().extend(snapshot.new_cookies.into_iter());
+            let mut removed = root.removed_cookies.borrow_mut();
+            for cookie in snapshot.removed_cookies.into_iter() {
+                removed.insert(cookie.name.clone(), cookie);
+            }
+        }
+        jar
+    }
+
+    /// Takes a snapshot of the state of this jar, including the changes which
+    /// `delta` will report.
+    ///
+    /// Cookies are included as they are stored, so signed and encrypted
+    /// cookies remain so. Each list is sorted by name.
+    #[cfg(feature = "serialize")]
+    pub fn snapshot(&self) -> JarSnapshot {
+        let root = self.root();
+        let mut cookies = root.map.borrow().values().cloned().collect::<Vec<_>>();
+        cookies.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut new_cookies = root.new_cookies.borrow().iter().cloned()
+                                  .collect::<Vec<_>>();
+        new_cookies.sort();
+        let mut removed_cookies = root.removed_cookies.borrow().values().cloned()
+                                      .collect::<Vec<_>>();
+        removed_cookies.sort_by(|a, b| a.name.cmp(&b.name));
+        JarSnapshot {
+            cookies: cookies,
+            new_cookies: new_cookies,
+            removed_cookies: removed_cookies,
+        }
+    }
+
     fn root<'a>(&'a self) -> &'a Root {
         let mut cur = self;
         loop {
@@ -128,11 +225,111 @@ impl<'a> CookieJar<'a> {
         }
     }
 
+    /// Adds a retired key to this jar, for rotating the signing key.
+    ///
+    /// Signed and encrypted cookies are still accepted if they were signed
+    /// with a retired key, but new cookies are always signed with the key the
+    /// jar was created with. Once `set_reissue_template` has been called, a
+    /// cookie found with a retired key is added again through the jar it was
+    /// found with, so `delta` re-issues it under the current key. Retired keys
+    /// are tried in the order they were added.
+    ///
+    /// This method only works on the root cookie jar.
+    pub fn add_retired_key(&mut self, key: &[u8]) {
+        match self.flavor {
+            Flavor::Child(..) => panic!("can't add a retired key to a child jar!"),
+            Flavor::Root(ref mut root) => {
+                root.keys.push(secure::Keys::new(normalize_key(key)));
+            }
+        }
+    }
+
+    /// Sets whether signed and encrypted cookies which were written using the
+    /// jar's keys directly, rather than keys derived from them, are accepted.
+    ///
+    /// Earlier versions used the same key both to sign and to encrypt. Turning
+    /// this on allows for migrating: such cookies are re-issued in the new
+    /// format in the same way as cookies signed with a retired key. The
+    /// default is off.
+    pub fn set_legacy_keys(&self, accept: bool) {
+        self.root().legacy_keys.set(accept);
+    }
+
+    /// Sets whether the private jar also accepts cookies written by the
+    /// encrypted jar, for migrating from one to the other. Such cookies are
+    /// re-issued by the private jar in the same way as cookies signed with a
+    /// retired key. The default is off.
+    pub fn set_legacy_encryption(&self, accept: bool) {
+        self.root().legacy_encryption.set(accept);
+    }
+
+    /// Sets how cookies named with the `__Secure-` or `__Host-` prefixes are
+    /// treated when they are added to this jar or any of its children.
+    pub fn set_prefix_policy(&self, policy: PrefixPolicy) {
+        self.root().prefix_policy.set(policy);
+    }
+
+    /// Sets the limits cookies added to this jar or any of its children must
+    /// stay within, or `None` to add cookies whatever their size.
+    ///
+    /// The default is `None`, and `discarded` can be used to find the cookies
+    /// a browser would drop.
+    pub fn set_limits(&self, limits: Option<Limits>) {
+        self.root().limits.set(limits);
+    }
+
+    /// Sets the attributes cookies are given when they are re-issued because
+    /// they were signed with a retired key or are in a legacy format, or
+    /// `None` to not re-issue them.
+    ///
+    /// A cookie sent by the browser only has a name and value, so one which
+    /// is re-issued takes everything else from `template`: its `Domain`,
+    /// `Path`, `Secure`, `HttpOnly` and so on. The name and value of
+    /// `template` are ignored. Cookies are re-issued as they are read by
+    /// `find` or `iter`, through the jar they were read with.
+    ///
+    /// The default is `None`, as re-issuing a cookie with the wrong `Domain`
+    /// or `Path` would leave the browser with two of them. Use
+    /// `needs_reissue` to re-issue cookies with attributes of their own.
+    ///
+    /// ```
+    /// use cookie::{Cookie, CookieJar};
+    ///
+    /// let mut jar = CookieJar::new(b"the new key, which is 32 bytes!!");
+    /// jar.add_retired_key(b"the old key, which is 32 bytes!!");
+    /// let mut template = Cookie::new(String::new(), String::new());
+    /// template.secure = true;
+    /// template.httponly = true;
+    /// jar.set_reissue_template(Some(template));
+    /// ```
+    pub fn set_reissue_template(&self, template: Option<Cookie>) {
+        *self.root().reissue_template.borrow_mut() = template;
+    }
+
     /// Adds a new cookie to this cookie jar.
     ///
     /// If this jar is a child cookie jar, this will walk up the chain of
     /// borrowed jars, modifying the cookie as it goes along.
-    pub fn add(&self, mut cookie: Cookie) {
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cookie is rejected, either because it exceeds limits set
+    /// with `set_limits` or because the jar's prefix policy is
+    /// `PrefixPolicy::Reject`. Neither is the default. Use `try_add` to handle
+    /// this.
+    pub fn add(&self, cookie: Cookie) {
+        match self.try_add(cookie) {
+            Ok(()) => {}
+            Err(e) => panic!("cookie rejected by jar: {}", e),
+        }
+    }
+
+    /// Adds a new cookie to this cookie jar, returning an error if the jar
+    /// rejects it.
+    ///
+    /// The limits are checked after any signing or encryption by child jars,
+    /// as it is that cookie which is sent to the browser.
+    pub fn try_add(&self, mut cookie: Cookie) -> Result<(), ValidationError> {
         let mut cur = self;
         let root = self.root();
         loop {
@@ -144,29 +341,121 @@ impl<'a> CookieJar<'a> {
                 Flavor::Root(..) => break,
             }
         }
+        match root.prefix_policy.get() {
+            PrefixPolicy::Ignore => {}
+            PrefixPolicy::Reject => try!(cookie.validate_prefix()),
+            PrefixPolicy::Fix => fix_prefix(&mut cookie),
+        }
+        match root.limits.get() {
+            Some(limits) => try!(limits.check(&cookie)),
+            None => {}
+        }
         let name = cookie.name.clone();
         root.map.borrow_mut().insert(name.clone(), cookie);
         root.removed_cookies.borrow_mut().remove(&name);
         root.new_cookies.borrow_mut().insert(name);
+        return Ok(());
+
+        fn fix_prefix(cookie: &mut Cookie) {
+            loop {
+                match cookie.validate_prefix() {
+                    Ok(()) => return,
+                    Err(ValidationError::HostPrefixWithDomain) => {
+                        cookie.domain = None;
+                    }
+                    Err(ValidationError::HostPrefixWithoutRootPath) => {
+                        cookie.path = Some("/".to_string());
+                    }
+                    Err(..) => cookie.secure = true,
+                }
+            }
+        }
     }
 
     /// Removes a cookie from this cookie jar.
+    ///
+    /// Browsers only delete a cookie whose domain and path match, so the
+    /// removal cookie reported by `delta` takes them from the cookie in the
+    /// jar. Cookies sent by the browser carry neither, so they are assumed to
+    /// have been set with a path of `/` and no domain; use `remove_with` if
+    /// that is wrong.
     pub fn remove(&self, cookie: &str) {
+        let name = cookie.to_string();
+        let existing = self.root().map.borrow().get(&name).map(|c| c.clone());
+        self.remove_with(existing.unwrap_or_else(|| Cookie::new(name, String::new())));
+    }
+
+    /// Removes a cookie from this cookie jar, deleting it from the browser
+    /// using the `Domain`, `Path`, `Secure` and `Partitioned` attributes of
+    /// `cookie`.
+    pub fn remove_with(&self, cookie: Cookie) {
         let root = self.root();
-        let cookie = cookie.to_string();
-        root.map.borrow_mut().remove(&cookie);
-        root.new_cookies.borrow_mut().remove(&cookie);
-        root.removed_cookies.borrow_mut().insert(cookie);
+        let name = cookie.name.clone();
+        root.map.borrow_mut().remove(&name);
+        root.new_cookies.borrow_mut().remove(&name);
+        root.removed_cookies.borrow_mut().insert(name, removal(cookie));
+
+        // A `Secure` or `Partitioned` cookie can only be replaced by one
+        // which has the same attribute.
+        fn removal(cookie: Cookie) -> Cookie {
+            let mut c = Cookie::new(cookie.name, String::new());
+            c.domain = cookie.domain;
+            c.path = cookie.path;
+            c.secure = cookie.secure;
+            c.partitioned = cookie.partitioned;
+            c.max_age = Some(0);
+            c.expires = Some(time::at_utc(Timespec::new(0, 0)));
+            c
+        }
     }
 
     /// Finds a cookie inside of this cookie jar.
     ///
     /// The cookie is subject to modification by any of the child cookie jars
     /// that are currently borrowed. A copy of the cookie is returned.
+    ///
+    /// A cookie signed with a retired key is re-issued under the current key,
+    /// see `set_reissue_template`.
     pub fn find(&self, name: &str) -> Option<Cookie> {
+        self.find_stale(name).map(|(cookie, stale)| {
+            if stale { self.reissue(&cookie) }
+            cookie
+        })
+    }
+
+    /// Returns whether the cookie `name` can be read through this jar, but
+    /// was signed with a retired key or is in a legacy format accepted
+    /// because of `set_legacy_keys` or `set_legacy_encryption`.
+    ///
+    /// Such a cookie should be added again through this jar to move it to the
+    /// current key and format. `find` and `iter` do this once
+    /// `set_reissue_template` has been called. Without a template, this tells
+    /// which cookies to add again with attributes of their own. It stops
+    /// returning `true` once the cookie has been added again.
+    ///
+    /// ```
+    /// use cookie::CookieJar;
+    ///
+    /// let mut jar = CookieJar::new(b"the new key, which is 32 bytes!!");
+    /// jar.add_retired_key(b"the old key, which is 32 bytes!!");
+    /// if jar.signed().needs_reissue("session") {
+    ///     let mut c = jar.signed().find("session").unwrap();
+    ///     c.secure = true;
+    ///     c.httponly = true;
+    ///     jar.signed().add(c);
+    /// }
+    /// ```
+    pub fn needs_reissue(&self, name: &str) -> bool {
+        match self.find_stale(name) {
+            Some((_, stale)) => stale,
+            None => false,
+        }
+    }
+
+    fn find_stale(&self, name: &str) -> Option<(Cookie, bool)> {
         let name = name.to_string();
         let root = self.root();
-        if root.removed_cookies.borrow().contains(&name) {
+        if root.removed_cookies.borrow().contains_key(&name) {
             return None
         }
         root.map.borrow().get(&name).and_then(|c| self.try_read(root, c.clone()))
@@ -191,6 +480,9 @@ impl<'a> CookieJar<'a> {
     /// All cookies read from the child jar must be encrypted and signed by a
     /// valid key and all cookies written will be encrypted and signed
     /// automatically.
+    ///
+    /// This uses AES-256-CBC and HMAC-SHA1. New code should use `private`,
+    /// which produces shorter values.
     pub fn encrypted<'a>(&'a self) -> CookieJar<'a> {
         return CookieJar {
             flavor: Flavor::Child(Child {
@@ -201,6 +493,24 @@ impl<'a> CookieJar<'a> {
         };
     }
 
+    /// Creates a child private cookie jar.
+    ///
+    /// Cookies written to the child jar are encrypted and authenticated with
+    /// AES-256-GCM under a random nonce, and cookies read from it must have
+    /// been. The cookie's name is authenticated too, so a value can't be
+    /// moved to another cookie. Values are encoded in unpadded base64url.
+    ///
+    /// See `set_legacy_encryption` for reading cookies from `encrypted`.
+    pub fn private<'a>(&'a self) -> CookieJar<'a> {
+        return CookieJar {
+            flavor: Flavor::Child(Child {
+                parent: self,
+                read: secure::unseal,
+                write: secure::seal,
+            })
+        };
+    }
+
     /// Creates a child jar for permanent cookie storage.
     ///
     /// All cookies written to the child jar will have an expiration date 20
@@ -214,14 +524,14 @@ impl<'a> CookieJar<'a> {
             })
         };
 
-        fn read(_root: &Root, cookie: Cookie) -> Option<Cookie> {
-            Some(cookie)
+        fn read(_root: &Root, cookie: Cookie) -> Option<(Cookie, bool)> {
+            Some((cookie, false))
         }
 
         fn write(_root: &Root, mut cookie: Cookie) -> Cookie {
             // Expire 20 years in the future
             cookie.max_age = Some(3600 * 24 * 365 * 20);
-            let mut now = time::now();
+            let mut now = time::now_utc();
             now.tm_year += 20;
             cookie.expires = Some(now);
             cookie
@@ -230,16 +540,14 @@ impl<'a> CookieJar<'a> {
 
     /// Calculates the changes that have occurred to this cookie jar over time,
     /// returning a vector of `Set-Cookie` headers.
+    ///
+    /// `SetCookieHeader` renders these while checking that none of them can
+    /// inject headers or attributes into the response.
     pub fn delta(&self) -> Vec<Cookie> {
         let mut ret = Vec::new();
         let root = self.root();
-        for cookie in root.removed_cookies.borrow().iter() {
-            let mut c = Cookie::new(cookie.clone(), String::new());
-            c.max_age = Some(0);
-            let mut now = time::now();
-            now.tm_year -= 1;
-            c.expires = Some(now);
-            ret.push(c);
+        for cookie in root.removed_cookies.borrow().values() {
+            ret.push(cookie.clone());
         }
         let map = root.map.borrow();
         for cookie in root.new_cookies.borrow().iter() {
@@ -248,17 +556,57 @@ impl<'a> CookieJar<'a> {
         return ret;
     }
 
-    fn try_read(&self, root: &Root, mut cookie: Cookie) -> Option<Cookie> {
+    /// Returns the cookies in `delta` which a browser would silently ignore,
+    /// along with the reason, checking them against this jar's limits or, if
+    /// none are set, `Limits::default()`.
+    ///
+    /// `try_add` rejects such cookies when limits are set, but they can still
+    /// end up in the jar if they were added before the limits were lowered,
+    /// were restored from a snapshot, or have an invalid prefix under
+    /// `PrefixPolicy::Ignore`.
+    pub fn discarded(&self) -> Vec<(Cookie, ValidationError)> {
+        let limits = self.root().limits.get().unwrap_or_default();
+        self.delta().into_iter().filter_map(|c| {
+            let valid = limits.check(&c).and_then(|()| c.validate())
+                              .and_then(|()| c.validate_prefix());
+            match valid {
+                Ok(()) => None,
+                Err(e) => Some((c, e)),
+            }
+        }).collect()
+    }
+
+    // Adds `cookie` again through this jar with the attributes of the reissue
+    // template, if there is one. This mustn't be called with any of the
+    // root's cells borrowed.
+    fn reissue(&self, cookie: &Cookie) {
+        let mut c = match *self.root().reissue_template.borrow() {
+            Some(ref template) => template.clone(),
+            None => return,
+        };
+        c.name = cookie.name.clone();
+        c.value = cookie.value.clone();
+        c.quoted = cookie.quoted;
+        // The cookie is still valid if this fails, it just isn't moved to the
+        // current key yet.
+        let _ = self.try_add(c);
+    }
+
+    // Reads `cookie` through this jar and its parents, also returning whether
+    // any of them reported it as needing to be issued again.
+    fn try_read(&self, root: &Root, mut cookie: Cookie) -> Option<(Cookie, bool)> {
         let mut jar = self;
+        let mut stale = false;
         loop {
             match jar.flavor {
                 Flavor::Child(Child { read, parent, .. }) => {
                     cookie = match read(root, cookie) {
-                        Some(c) => c, None => return None,
+                        Some((c, s)) => { stale |= s; c }
+                        None => return None,
                     };
                     jar = parent;
                 }
-                Flavor::Root(..) => return Some(cookie),
+                Flavor::Root(..) => return Some((cookie, stale)),
             }
         }
     }
@@ -283,34 +631,81 @@ impl<'a> Iterator<Cookie> for Iter<'a> {
                 None => return None,
             };
             let root = self.jar.root();
-            let map = root.map.borrow();
-            let cookie = match map.get(&key) {
+            let cookie = match root.map.borrow().get(&key) {
                 Some(cookie) => cookie.clone(),
                 None => continue,
             };
             match self.jar.try_read(root, cookie) {
-                Some(cookie) => return Some(cookie),
+                Some((cookie, stale)) => {
+                    if stale { self.jar.reissue(&cookie) }
+                    return Some(cookie)
+                }
                 None => {}
             }
         }
     }
 }
 
+fn normalize_key(key: &[u8]) -> Vec<u8> {
+    if key.len() >= secure::MIN_KEY_LEN {
+        key.to_vec()
+    } else {
+        // Using a SHA-256 hash to normalize key as Rails suggests.
+        // See https://github.com/rails/rails/blob/master/activesupport/lib/active_support/message_encryptor.rb
+        secure::prepare_key(key)
+    }
+}
+
 mod secure {
     use jar::{Root};
     use {Cookie};
-    use openssl::crypto::{hmac, hash, memcmp, symm};
+    use aead;
+    use openssl::crypto::{hmac, hash, memcmp, rand, symm};
+    use serialize::base64::{FromBase64, ToBase64, URL_SAFE};
     use serialize::hex::{ToHex, FromHex};
 
     pub const MIN_KEY_LEN: uint = 32;
 
+    // The keys used for each purpose, derived from one of a jar's keys.
+    pub struct Keys {
+        // The key as given, which used to be used for everything.
+        master: Vec<u8>,
+        signing: Vec<u8>,
+        encryption: Vec<u8>,
+        private: Vec<u8>,
+    }
+
+    impl Keys {
+        pub fn new(master: Vec<u8>) -> Keys {
+            Keys {
+                signing: hkdf(master.as_slice(), "cookie signing"),
+                encryption: hkdf(master.as_slice(), "cookie encryption"),
+                private: hkdf(master.as_slice(), "cookie private"),
+                master: master,
+            }
+        }
+    }
+
+    // HKDF from RFC 5869 using HMAC-SHA256 and no salt, producing a 32 byte
+    // key for the purpose named by `info`.
+    pub fn hkdf(key: &[u8], info: &str) -> Vec<u8> {
+        let mut extract = hmac::HMAC(hash::HashType::SHA256, &[]);
+        extract.update(key);
+        let prk = extract.finalize();
+
+        let mut expand = hmac::HMAC(hash::HashType::SHA256, prk.as_slice());
+        expand.update(info.as_bytes());
+        expand.update(&[1]);
+        expand.finalize()
+    }
+
     // If a SHA1 HMAC is good enough for rails, it's probably good enough
     // for us as well:
     //
     // https://github.com/rails/rails/blob/master/activesupport/lib
     //                   /active_support/message_verifier.rb#L70
     pub fn sign(root: &Root, mut cookie: Cookie) -> Cookie {
-        let signature = dosign(root, cookie.value.as_slice());
+        let signature = dosign(root.keys[0].signing.as_slice(), cookie.value.as_slice());
         cookie.value.push_str("--");
         cookie.value.push_str(signature.as_slice().to_hex().as_slice());
         cookie
@@ -332,23 +727,46 @@ mod secure {
         Some((text, ext))
     }
 
-    pub fn design(root: &Root, mut cookie: Cookie) -> Option<Cookie> {
-        let len = {
+    pub fn design(root: &Root, cookie: Cookie) -> Option<(Cookie, bool)> {
+        design_with_key(root, cookie).map(|(cookie, key, legacy)| {
+            (cookie, key != 0 || legacy)
+        })
+    }
+
+    // Strips the signature from `cookie`, returning the index of the key in
+    // `root.keys` which it was made with and whether it was used directly.
+    fn design_with_key(root: &Root, mut cookie: Cookie) -> Option<(Cookie, uint, bool)> {
+        let (len, key, legacy) = {
             let (text, signature) = match split_value(cookie.value.as_slice()) {
                 Some(pair) => pair, None => return None
             };
-            let expected = dosign(root, text);
-            if !memcmp::eq(expected.as_slice(), signature.as_slice()) {
-                return None
+            let mut found = None;
+            for (i, keys) in root.keys.iter().enumerate() {
+                if verify(keys.signing.as_slice(), text, signature.as_slice()) {
+                    found = Some((i, false));
+                } else if root.legacy_keys.get() &&
+                          verify(keys.master.as_slice(), text, signature.as_slice()) {
+                    found = Some((i, true));
+                }
+                if found.is_some() { break }
+            }
+            match found {
+                Some((key, legacy)) => (text.len(), key, legacy),
+                None => return None,
             }
-            text.len()
         };
         cookie.value.truncate(len);
-        Some(cookie)
+        return Some((cookie, key, legacy));
+
+        fn verify(key: &[u8], text: &str, signature: &[u8]) -> bool {
+            let expected = dosign(key, text);
+            expected.len() == signature.len() &&
+                memcmp::eq(expected.as_slice(), signature)
+        }
     }
 
-    fn dosign(root: &Root, val: &str) -> Vec<u8> {
-        let mut hmac = hmac::HMAC(hash::HashType::SHA1, root.key.as_slice());
+    fn dosign(key: &[u8], val: &str) -> Vec<u8> {
+        let mut hmac = hmac::HMAC(hash::HashType::SHA1, key);
         hmac.update(val.as_bytes());
         hmac.finalize()
     }
@@ -356,18 +774,19 @@ mod secure {
     // Implementation details were taken from Rails. See
     // https://github.com/rails/rails/blob/master/activesupport/lib/active_support/message_encryptor.rb#L57
     pub fn encrypt_and_sign(root: &Root, mut cookie: Cookie) -> Cookie {
-        let encrypted_data = encrypt_data(root, cookie.value.as_slice());
+        let encrypted_data = encrypt_data(root.keys[0].encryption.as_slice(),
+                                          cookie.value.as_slice());
         cookie.value = encrypted_data;
         sign(root, cookie)
     }
 
-    fn encrypt_data(root: &Root, val: &str) -> String {
+    fn encrypt_data(key: &[u8], val: &str) -> String {
         let iv = random_iv();
         let iv_str = iv.as_slice().to_hex();
 
         let mut encrypted_data = symm::encrypt(
             symm::Type::AES_256_CBC,
-            root.key.as_slice().slice_to(MIN_KEY_LEN),
+            key.slice_to(MIN_KEY_LEN),
             iv,
             val.as_bytes()
         ).as_slice().to_hex();
@@ -377,20 +796,23 @@ mod secure {
         encrypted_data
     }
 
-    pub fn design_and_decrypt(root: &Root, cookie: Cookie) -> Option<Cookie> {
-        let mut cookie = match design(root, cookie) {
-            Some(cookie) => cookie,
+    pub fn design_and_decrypt(root: &Root, cookie: Cookie) -> Option<(Cookie, bool)> {
+        let (mut cookie, key, legacy) = match design_with_key(root, cookie) {
+            Some(found) => found,
             None => return None
         };
 
-        let decrypted_data = decrypt_data(root, cookie.value.as_slice()).and_then(|data| String::from_utf8(data).ok());
+        let keys = &root.keys[key];
+        let key_data = if legacy { &keys.master } else { &keys.encryption };
+        let decrypted_data = decrypt_data(key_data.as_slice(), cookie.value.as_slice())
+                                 .and_then(|data| String::from_utf8(data).ok());
         match decrypted_data {
-            Some(val) => { cookie.value = val; Some(cookie) },
+            Some(val) => { cookie.value = val; Some((cookie, key != 0 || legacy)) },
             None => return None
         }
     }
 
-    fn decrypt_data(root: &Root, val: &str) -> Option<Vec<u8>> {
+    fn decrypt_data(key: &[u8], val: &str) -> Option<Vec<u8>> {
         let (val, iv) = match split_value(val) {
             Some(pair) => pair, None => return None
         };
@@ -398,17 +820,64 @@ mod secure {
         let actual = match val.as_slice().from_hex() {
             Ok(actual) => actual, Err(_) => return None
         };
+        // OpenSSL asserts on an IV of the wrong size, and anything which
+        // isn't a whole number of blocks can't have come from `encrypt_data`.
+        if iv.len() != 16 || actual.len() == 0 || actual.len() % 16 != 0 {
+            return None
+        }
 
         Some(symm::decrypt(
             symm::Type::AES_256_CBC,
-            root.key.as_slice().slice_to(MIN_KEY_LEN),
+            key.slice_to(MIN_KEY_LEN),
             iv,
             actual.as_slice()
         ))
     }
 
     fn random_iv() -> Vec<u8> {
-        ::openssl::crypto::rand::rand_bytes(16)
+        rand::rand_bytes(16)
+    }
+
+    // Values are the nonce followed by the ciphertext and tag.
+    pub fn seal(root: &Root, mut cookie: Cookie) -> Cookie {
+        let mut data = rand::rand_bytes(aead::NONCE_LEN);
+        let sealed = aead::seal(root.keys[0].private.as_slice(), data.as_slice(),
+                                cookie.name.as_bytes(), cookie.value.as_bytes());
+        data.push_all(sealed.as_slice());
+        cookie.value = data.as_slice().to_base64(URL_SAFE);
+        cookie
+    }
+
+    pub fn unseal(root: &Root, mut cookie: Cookie) -> Option<(Cookie, bool)> {
+        let opened = open(root, &cookie);
+        return match opened {
+            Some((value, key)) => {
+                cookie.value = value;
+                Some((cookie, key != 0))
+            }
+            None if root.legacy_encryption.get() => {
+                design_and_decrypt(root, cookie).map(|(cookie, _)| (cookie, true))
+            }
+            None => None,
+        };
+
+        // Returns the value along with the index of the key which opened it.
+        fn open(root: &Root, cookie: &Cookie) -> Option<(String, uint)> {
+            let data = match cookie.value.as_slice().from_base64() {
+                Ok(data) => data, Err(..) => return None,
+            };
+            if data.len() < aead::NONCE_LEN { return None }
+            let (nonce, sealed) = (data.slice_to(aead::NONCE_LEN),
+                                   data.slice_from(aead::NONCE_LEN));
+            for (i, keys) in root.keys.iter().enumerate() {
+                let name = cookie.name.as_bytes();
+                match aead::open(keys.private.as_slice(), nonce, name, sealed) {
+                    Some(value) => return String::from_utf8(value).ok().map(|v| (v, i)),
+                    None => {}
+                }
+            }
+            None
+        }
     }
 
     pub fn prepare_key(key: &[u8]) -> Vec<u8> {
@@ -418,7 +887,8 @@ mod secure {
 
 #[cfg(test)]
 mod test {
-    use {Cookie, CookieJar};
+    use std::default::Default;
+    use {Cookie, CookieJar, Limits, PrefixPolicy, ValidationError};
 
     const KEY: &'static [u8] = b"f8f9eaf1ecdedff5e5b749c58115441e";
 
@@ -469,6 +939,42 @@ mod test {
         secure_behaviour!(c, encrypted)
     }
 
+    #[test]
+    fn private() {
+        let c = CookieJar::new(KEY);
+        secure_behaviour!(c, private);
+
+        // Values can't be moved to another cookie.
+        let mut cookie = c.find("test").unwrap();
+        c.private().add(Cookie::new("test".to_string(), "test".to_string()));
+        cookie.name = "other".to_string();
+        cookie.value = c.find("test").unwrap().value;
+        c.add(cookie);
+        assert!(c.private().find("test").is_some());
+        assert!(c.private().find("other").is_none());
+    }
+
+    #[test]
+    fn legacy_encryption() {
+        let old = CookieJar::new(KEY);
+        old.encrypted().add(Cookie::new("e".to_string(), "1".to_string()));
+
+        let mut c = CookieJar::new(KEY);
+        c.add_original(old.find("e").unwrap());
+        assert!(c.private().find("e").is_none());
+
+        c.set_legacy_encryption(true);
+        assert!(c.private().needs_reissue("e"));
+        c.set_reissue_template(Some(Cookie::new(String::new(), String::new())));
+        assert_eq!(c.private().find("e").unwrap().value.as_slice(), "1");
+
+        // It was re-issued in the new format.
+        assert!(!c.private().needs_reissue("e"));
+        let mut current = CookieJar::new(KEY);
+        current.add_original(c.delta().pop().unwrap());
+        assert_eq!(current.private().find("e").unwrap().value.as_slice(), "1");
+    }
+
     #[test]
     fn permanent() {
         let c = CookieJar::new(KEY);
@@ -495,6 +1001,281 @@ mod test {
         assert!(cookie.max_age.is_some());
     }
 
+    #[test]
+    fn retired_keys() {
+        const OLD: &'static [u8] = b"0123456789abcdef0123456789abcdef";
+
+        let old = CookieJar::new(OLD);
+        old.signed().add(Cookie::new("s".to_string(), "1".to_string()));
+        old.encrypted().add(Cookie::new("e".to_string(), "2".to_string()));
+
+        let mut c = CookieJar::new(KEY);
+        c.add_retired_key(b"unrelated");
+        c.add_retired_key(OLD);
+        for cookie in old.delta().into_iter() {
+            c.add_original(cookie);
+        }
+        assert_eq!(c.signed().find("s").unwrap().value.as_slice(), "1");
+        assert_eq!(c.encrypted().find("e").unwrap().value.as_slice(), "2");
+        assert!(c.signed().find("e").is_none());
+        assert!(c.signed().needs_reissue("s"));
+        assert!(c.encrypted().needs_reissue("e"));
+        assert!(!c.signed().needs_reissue("e"));
+        assert!(c.delta().is_empty());
+
+        // Once added again, both are under the primary key.
+        let cookie = c.signed().find("s").unwrap();
+        c.signed().add(cookie);
+        let cookie = c.encrypted().find("e").unwrap();
+        c.encrypted().add(cookie);
+        assert!(!c.signed().needs_reissue("s"));
+        let mut current = CookieJar::new(KEY);
+        let delta = c.delta();
+        assert_eq!(delta.len(), 2);
+        for cookie in delta.into_iter() {
+            current.add_original(cookie);
+        }
+        assert_eq!(current.signed().find("s").unwrap().value.as_slice(), "1");
+        assert_eq!(current.encrypted().find("e").unwrap().value.as_slice(), "2");
+
+        // Without the retired key, the old cookies are rejected.
+        let mut current = CookieJar::new(KEY);
+        for cookie in old.delta().into_iter() {
+            current.add_original(cookie);
+        }
+        assert!(current.signed().find("s").is_none());
+        assert!(current.encrypted().find("e").is_none());
+        assert!(!current.signed().needs_reissue("s"));
+    }
+
+    #[test]
+    fn reissue_from_headers() {
+        const OLD: &'static [u8] = b"0123456789abcdef0123456789abcdef";
+
+        let old = CookieJar::new(OLD);
+        let mut session = Cookie::new("session".to_string(), "42".to_string());
+        session.secure = true;
+        session.httponly = true;
+        old.signed().add(session);
+        let header = format!("session={}", old.find("session").unwrap().value);
+
+        // Without a template, reading never changes what is sent back.
+        let mut c = CookieJar::from_headers(KEY, &[header.as_slice()]);
+        c.add_retired_key(OLD);
+        assert_eq!(c.signed().find("session").unwrap().value.as_slice(), "42");
+        assert_eq!(c.signed().iter().count(), 1);
+        assert!(c.delta().is_empty());
+        assert!(c.signed().needs_reissue("session"));
+
+        // With one, the cookie is re-issued as it is read, taking its
+        // attributes from the template.
+        let mut template = Cookie::new("ignored".to_string(), "ignored".to_string());
+        template.secure = true;
+        template.httponly = true;
+        template.domain = Some("example.com".to_string());
+        c.set_reissue_template(Some(template));
+        assert_eq!(c.signed().iter().next().unwrap().value.as_slice(), "42");
+        assert!(!c.signed().needs_reissue("session"));
+        let delta = c.delta();
+        assert_eq!(delta.len(), 1);
+        assert_eq!(delta[0].name.as_slice(), "session");
+        assert!(delta[0].secure && delta[0].httponly);
+        assert_eq!(delta[0].domain, Some("example.com".to_string()));
+        assert_eq!(delta[0].path, Some("/".to_string()));
+
+        let mut current = CookieJar::new(KEY);
+        current.add_original(delta[0].clone());
+        assert_eq!(current.signed().find("session").unwrap().value.as_slice(), "42");
+    }
+
+    #[test]
+    fn hkdf() {
+        use serialize::hex::ToHex;
+
+        // RFC 5869, test case 3, truncated to 32 bytes.
+        assert_eq!(super::secure::hkdf(&[0x0b, ..22], "").as_slice().to_hex().as_slice(),
+                   "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d");
+    }
+
+    #[test]
+    fn legacy_keys() {
+        use openssl::crypto::{hash, hmac};
+        use serialize::hex::ToHex;
+
+        // A value signed with the key itself, as earlier versions did.
+        let mut hmac = hmac::HMAC(hash::HashType::SHA1, KEY);
+        hmac.update(b"1");
+        let value = format!("1--{}", hmac.finalize().as_slice().to_hex());
+
+        let mut c = CookieJar::new(KEY);
+        c.add_original(Cookie::new("s".to_string(), value));
+        assert!(c.signed().find("s").is_none());
+
+        c.set_legacy_keys(true);
+        assert_eq!(c.signed().find("s").unwrap().value.as_slice(), "1");
+        assert!(c.signed().needs_reissue("s"));
+        assert!(c.delta().is_empty());
+
+        let cookie = c.signed().find("s").unwrap();
+        c.signed().add(cookie);
+        let mut current = CookieJar::new(KEY);
+        current.add_original(c.delta().pop().unwrap());
+        assert_eq!(current.signed().find("s").unwrap().value.as_slice(), "1");
+    }
+
+    #[test]
+    fn from_headers() {
+        let c = CookieJar::from_headers(KEY, &["a=1; b=2", "a=3; c="]);
+        assert_eq!(c.find("a").unwrap().value.as_slice(), "1");
+        assert_eq!(c.find("b").unwrap().value.as_slice(), "2");
+        assert_eq!(c.find("c").unwrap().value.as_slice(), "");
+        assert_eq!(c.iter().count(), 3);
+        assert!(c.delta().is_empty());
+
+        let c = CookieJar::from_headers(KEY, &["tracker=%FF; lonely; session=1"]);
+        assert_eq!(c.find("tracker").unwrap().value.as_slice(), "%FF");
+        assert_eq!(c.find("session").unwrap().value.as_slice(), "1");
+        assert!(c.find("").is_none());
+        assert_eq!(c.iter().count(), 2);
+    }
+
+    #[test]
+    fn prefix_policy() {
+        let host = || {
+            let mut c = Cookie::new("__Host-id".to_string(), "1".to_string());
+            c.domain = Some("foo.com".to_string());
+            c.path = Some("/foo".to_string());
+            c
+        };
+
+        let c = CookieJar::new(KEY);
+        c.add(host());
+        assert!(!c.find("__Host-id").unwrap().secure);
+
+        c.set_prefix_policy(PrefixPolicy::Reject);
+        assert_eq!(c.signed().try_add(host()),
+                   Err(ValidationError::HostPrefixWithoutSecure));
+        assert!(c.try_add(Cookie::new("plain".to_string(), "1".to_string()))
+                 .is_ok());
+
+        c.set_prefix_policy(PrefixPolicy::Fix);
+        c.add(host());
+        let cookie = c.find("__Host-id").unwrap();
+        assert!(cookie.secure);
+        assert_eq!(cookie.domain, None);
+        assert_eq!(cookie.path, Some("/".to_string()));
+
+        c.add(Cookie::new("__Secure-id".to_string(), "1".to_string()));
+        assert!(c.find("__Secure-id").unwrap().secure);
+    }
+
+    #[test]
+    #[should_fail]
+    fn prefix_policy_reject_panics() {
+        let c = CookieJar::new(KEY);
+        c.set_prefix_policy(PrefixPolicy::Reject);
+        c.add(Cookie::new("__Secure-id".to_string(), "1".to_string()));
+    }
+
+    #[test]
+    fn removal_attributes() {
+        let c = CookieJar::new(KEY);
+        let mut cookie = Cookie::new("a".to_string(), "1".to_string());
+        cookie.path = Some("/app".to_string());
+        cookie.domain = Some("example.com".to_string());
+        cookie.secure = true;
+        c.add(cookie);
+        c.remove("a");
+        let delta = c.delta();
+        assert_eq!(delta.len(), 1);
+        assert_eq!(delta[0].to_string().as_slice(),
+                   "a=; Secure; Path=/app; Domain=example.com; Max-Age=0; \
+                    Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+
+        let mut c = CookieJar::new(KEY);
+        c.add_original(Cookie::new("b".to_string(), "2".to_string()));
+        let mut cookie = Cookie::new("b".to_string(), String::new());
+        cookie.path = Some("/sub".to_string());
+        c.remove_with(cookie);
+        assert!(c.find("b").is_none());
+        assert_eq!(c.delta()[0].to_string().as_slice(),
+                   "b=; Path=/sub; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+
+        let c = CookieJar::new(KEY);
+        c.remove("c");
+        assert_eq!(c.delta()[0].to_string().as_slice(),
+                   "c=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+
+    #[test]
+    fn limits() {
+        // By default anything is added, and only reported by `discarded`.
+        let c = CookieJar::new(KEY);
+        c.add(Cookie::new("a".to_string(), String::from_char(4096, 'a')));
+        c.add(Cookie::new("b".to_string(), "1\r\n2".to_string()));
+        assert_eq!(c.discarded().into_iter().map(|(c, e)| (c.name, e)).collect::<Vec<_>>(),
+                   vec![("a".to_string(), ValidationError::TooLarge(4097))]);
+
+        let c = CookieJar::new(KEY);
+        c.set_limits(Some(Default::default()));
+        let big = String::from_char(4096, 'a');
+        assert_eq!(c.try_add(Cookie::new("a".to_string(), big.clone())),
+                   Err(ValidationError::TooLarge(4097)));
+        assert!(c.find("a").is_none());
+
+        // The signature counts towards the size.
+        let value = String::from_char(4096 - 43, 'a');
+        assert!(c.try_add(Cookie::new("a".to_string(), value.clone())).is_ok());
+        assert!(c.signed().try_add(Cookie::new("a".to_string(), value + "a"))
+                 .is_err());
+
+        c.set_limits(Some(Limits { max_name_value_len: 8192, ..Default::default() }));
+        c.add(Cookie::new("b".to_string(), big));
+        c.set_limits(Some(Default::default()));
+        c.add(Cookie::new("__Secure-c".to_string(), "1".to_string()));
+
+        let mut discarded = c.discarded().into_iter().map(|(c, e)| (c.name, e))
+                             .collect::<Vec<_>>();
+        discarded.sort_by(|&(ref a, _), &(ref b, _)| a.cmp(b));
+        assert_eq!(discarded, vec![
+            ("__Secure-c".to_string(), ValidationError::SecurePrefixWithoutSecure),
+            ("b".to_string(), ValidationError::TooLarge(4097)),
+        ]);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn snapshot() {
+        use serialize::json;
+        use JarSnapshot;
+
+        let mut c = CookieJar::new(KEY);
+        c.add_original(Cookie::new("original".to_string(), "1".to_string()));
+        c.add_original(Cookie::new("gone".to_string(), "2".to_string()));
+        c.add(Cookie::new("new".to_string(), "3".to_string()));
+        c.signed().add(Cookie::new("signed".to_string(), "4".to_string()));
+        c.remove("gone");
+
+        let encoded = json::encode(&c.snapshot());
+        let snapshot = json::decode::<JarSnapshot>(encoded.as_slice()).unwrap();
+        assert_eq!(snapshot, c.snapshot());
+
+        let restored = CookieJar::from_snapshot(KEY, snapshot);
+        assert_eq!(restored.snapshot(), c.snapshot());
+        assert_eq!(restored.signed().find("signed").unwrap().value.as_slice(), "4");
+        assert!(restored.find("gone").is_none());
+
+        assert_eq!(names(&restored), names(&c));
+
+        fn names(jar: &CookieJar) -> Vec<(String, String)> {
+            let mut names = jar.delta().into_iter()
+                               .map(|c| (c.name, c.value))
+                               .collect::<Vec<_>>();
+            names.sort();
+            names
+        }
+    }
+
     #[test]
     fn iter() {
         let mut c = CookieJar::new(KEY);
@@ -5,26 +5,60 @@ extern crate url;
 extern crate time;
 extern crate openssl;
 extern crate serialize;
+extern crate libc;
 
+use std::ascii::AsciiExt;
+use std::cmp;
 use std::collections::TreeMap;
+use std::default::Default;
+use std::error::Error;
 use std::fmt;
+use std::i64;
 use std::str::FromStr;
+use std::string::CowString;
 
-pub use jar::CookieJar;
+use builder::is_token_char;
 
+#[cfg(feature = "serialize")]
+pub use browser::{BrowserFormat, BrowserJsonError, from_browser_json, to_browser_json};
+pub use builder::{CookieBuilder, Limits, MAX_NAME_VALUE_LEN, MAX_ATTR_VALUE_LEN};
+pub use header::{CookieHeader, SetCookieHeader};
+pub use jar::{CookieJar, PrefixPolicy};
+#[cfg(feature = "serialize")] pub use jar::JarSnapshot;
+pub use netscape::{CookiesTxt, CookiesTxtEntry, CookiesTxtError, CookiesTxtErrorKind};
+pub use raw::RawCookie;
+pub use date::{parse_cookie_date, format_cookie_date};
+pub use parse::{parse_cookie_header, parse_cookie_headers};
+
+mod aead;
+#[cfg(feature = "serialize")] mod browser;
+mod builder;
+#[cfg(feature = "serialize")] mod codec;
+mod date;
+mod header;
 mod jar;
+mod netscape;
+mod parse;
+mod raw;
 
 #[deriving(PartialEq, Clone)]
 pub struct Cookie {
     pub name: String,
     pub value: String,
+    /// Whether the value is wrapped in double quotes, which RFC 6265 allows.
+    /// The quotes aren't part of `value`.
+    pub quoted: bool,
     pub expires: Option<time::Tm>,
-    pub max_age: Option<u64>,
+    pub max_age: Option<i64>,
     pub domain: Option<String>,
     pub path: Option<String>,
     pub secure: bool,
     pub httponly: bool,
+    pub same_site: Option<SameSite>,
+    pub partitioned: bool,
+    pub priority: Option<Priority>,
     pub custom: TreeMap<String, String>,
+    pub legacy: Option<Legacy>,
 }
 
 
@@ -33,61 +67,230 @@ impl Cookie {
         Cookie {
             name: name,
             value: value,
+            quoted: false,
             expires: None,
             max_age: None,
             domain: None,
             path: Some("/".to_string()),
             secure: false,
             httponly: false,
+            same_site: None,
+            partitioned: false,
+            priority: None,
             custom: TreeMap::new(),
+            legacy: None,
         }
     }
 
-    pub fn parse(s: &str) -> Result<Cookie, ()> {
-        macro_rules! try_option( ($e:expr) => (
-            match $e { Some(s) => s, None => return Err(()) }
-        ) )
-
-        let mut c = Cookie::new(String::new(), String::new());
-        let mut pairs = s.trim().split(';');
-        let keyval = try_option!(pairs.next());
-        let (name, value) = try!(split(keyval));
-        let name = url::percent_decode(name.as_bytes());
-        let value = url::percent_decode(value.as_bytes());
-        c.name = try!(String::from_utf8(name).map_err(|_| ()));
-        c.value = try!(String::from_utf8(value).map_err(|_| ()));
-
-        for attr in pairs {
-            match attr.trim() {
-                "Secure" => c.secure = true,
-                "HttpOnly" => c.httponly = true,
-                s => {
-                    let (k, v) = try!(split(s));
-                    match k {
-                        "Max-Age" => c.max_age = Some(try_option!(from_str(v))),
-                        "Domain" => c.domain = Some(v.to_string()),
-                        "Path" => c.path = Some(v.to_string()),
-                        "Expires" => {
-                            let fmt = "%a, %d %b %Y %H:%M:%S %Z";
-                            let tm = try_option!(time::strptime(v, fmt).ok());
-                            c.expires = Some(tm);
-                        }
-                        _ => { c.custom.insert(k.to_string(), v.to_string()); }
-                    }
-                }
+    /// Resolves when this cookie expires, as a browser would at time `now`.
+    ///
+    /// `Max-Age` takes precedence over `Expires`, and a `Max-Age` of zero or
+    /// less expires the cookie at the earliest representable time. As RFC
+    /// 6265bis requires, no cookie lasts longer than `MAX_EXPIRY_SECS` after
+    /// `now`. A cookie with neither attribute lasts for the session.
+    pub fn expiration(&self, now: time::Timespec) -> Expiration {
+        if self.legacy.as_ref().map_or(false, |l| l.discard) {
+            return Expiration::Session
+        }
+        let latest = time::Timespec::new(now.sec + MAX_EXPIRY_SECS, now.nsec);
+        let at = match (self.max_age, &self.expires) {
+            (Some(n), _) if n <= 0 => time::Timespec::new(i64::MIN, 0),
+            (Some(n), _) if n >= MAX_EXPIRY_SECS => latest,
+            (Some(n), _) => time::Timespec::new(now.sec + n, now.nsec),
+            (None, &Some(ref tm)) => cmp::min(date::to_timespec(tm), latest),
+            (None, &None) => return Expiration::Session,
+        };
+        Expiration::At(at)
+    }
+
+    /// Converts a cookie parsed in `ParseMode::Legacy` to one which can be
+    /// sent in a `Set-Cookie` header.
+    ///
+    /// The RFC 2965 attributes are dropped. A cookie with `Discard` set
+    /// becomes a session cookie, and the port restriction is lost, as RFC
+    /// 6265 has no way to express it.
+    pub fn into_modern(mut self) -> Cookie {
+        match self.legacy.take() {
+            Some(ref l) if l.discard => {
+                self.max_age = None;
+                self.expires = None;
             }
+            _ => {}
         }
+        self
+    }
+
+    /// Returns whether this cookie has expired at time `now`.
+    ///
+    /// Session cookies never expire by this measure.
+    pub fn is_expired(&self, now: time::Timespec) -> bool {
+        match self.expiration(now) {
+            Expiration::At(t) => t <= now,
+            Expiration::Session => false,
+        }
+    }
+
+    /// Renders this cookie as the value of a `Set-Cookie` header.
+    ///
+    /// The value and custom attribute values are always percent-encoded. The
+    /// name, `Path`, `Domain` and custom attribute names are written as they
+    /// are, apart from any `%` in a name, so this fails if any of them
+    /// contains a character RFC 6265 section 4.1 doesn't allow there. The
+    /// `Show` implementation instead percent-encodes such characters.
+    pub fn render(&self) -> Result<String, ValidationError> {
+        try!(header::check_set_cookie(self));
+        Ok(self.to_string())
+    }
+
+    /// Creates a builder for a cookie with the given name and value.
+    ///
+    /// See `CookieBuilder` for details.
+    pub fn build(name: &str, value: &str) -> CookieBuilder {
+        CookieBuilder::new(name, value)
+    }
+
+    /// Parses a `Set-Cookie` header value in strict mode.
+    ///
+    /// See `ParseMode::Strict` for details.
+    pub fn parse(s: &str) -> Result<Cookie, ParseError> {
+        Cookie::parse_with(s, ParseMode::Strict)
+    }
 
-        return Ok(c);
+    /// Parses a `Set-Cookie` header value using the given parsing mode.
+    ///
+    /// In any mode, a header which exceeds the default `Limits` or contains a
+    /// control character is rejected with `ParseError::Invalid`, as a browser
+    /// would reject it. Strict mode also rejects a cookie which fails
+    /// `validate`, while the other modes leave that check to the caller.
+    pub fn parse_with(s: &str, mode: ParseMode) -> Result<Cookie, ParseError> {
+        Cookie::parse_with_limits(s, mode, &Default::default())
+    }
 
-        fn split<'a>(s: &'a str) -> Result<(&'a str, &'a str), ()> {
-            let mut parts = s.trim().splitn(1, '=');
-            Ok((try_option!(parts.next()), try_option!(parts.next())))
+    /// Parses a `Set-Cookie` header value using the given parsing mode,
+    /// rejecting it if it exceeds the given limits.
+    ///
+    /// The limits apply to the header as it was received, see
+    /// `Limits::check_header`.
+    pub fn parse_with_limits(s: &str, mode: ParseMode, limits: &Limits)
+                             -> Result<Cookie, ParseError> {
+        let c = try!(match mode {
+            ParseMode::Strict => parse::strict(s).map(|c| c.into_owned()),
+            ParseMode::Lenient => parse::lenient(s),
+            ParseMode::Legacy => parse::legacy(s),
+        });
+        let valid = limits.check_header(s).and_then(|()| match mode {
+            ParseMode::Strict => c.validate(),
+            ParseMode::Lenient | ParseMode::Legacy => Ok(()),
+        });
+        match valid {
+            Ok(()) => Ok(c),
+            Err(e) => Err(ParseError::Invalid { error: e, input: s.to_string() }),
         }
     }
 
-    pub fn pair(&self) -> AttrVal {
-        AttrVal(self.name.as_slice(), self.value.as_slice())
+    /// Checks that this cookie's attributes are consistent with one another.
+    ///
+    /// This doesn't check the requirements of a `__Secure-` or `__Host-`
+    /// prefix, see `validate_prefix`.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.partitioned && !self.secure {
+            return Err(ValidationError::PartitionedWithoutSecure)
+        }
+        Ok(())
+    }
+
+    /// Checks the requirements RFC 6265bis places on cookies whose names
+    /// start with `__Secure-` or `__Host-`.
+    ///
+    /// Both prefixes require `Secure`. A `__Host-` cookie must also have a
+    /// path of `/` and no domain. Browsers match the prefixes
+    /// case-insensitively, and so does this.
+    pub fn validate_prefix(&self) -> Result<(), ValidationError> {
+        if has_prefix(self.name.as_slice(), "__Secure-") && !self.secure {
+            return Err(ValidationError::SecurePrefixWithoutSecure)
+        }
+        if has_prefix(self.name.as_slice(), "__Host-") {
+            if !self.secure {
+                return Err(ValidationError::HostPrefixWithoutSecure)
+            }
+            if self.domain.is_some() {
+                return Err(ValidationError::HostPrefixWithDomain)
+            }
+            if self.path.as_ref().map(|s| s.as_slice()) != Some("/") {
+                return Err(ValidationError::HostPrefixWithoutRootPath)
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns this cookie's `name=value` pair, as sent in a `Cookie`
+    /// header.
+    pub fn pair(&self) -> Pair {
+        Pair(self)
+    }
+}
+
+/// A cookie which borrows from the string it was parsed from.
+///
+/// The name and value are only copied if they need to be percent-decoded,
+/// which makes this cheaper than `Cookie::parse` when most of the parsed
+/// cookies are thrown away. Parsing is always done in strict mode, and unlike
+/// `Cookie::parse` the attributes are not validated.
+///
+/// # Example
+///
+/// ```
+/// use cookie::CookieRef;
+///
+/// let c = CookieRef::parse("foo=bar; Path=/foo").unwrap();
+/// assert_eq!(c.name.as_slice(), "foo");
+/// assert_eq!(c.path, Some("/foo"));
+///
+/// let owned = c.into_owned();
+/// assert_eq!(owned.value.as_slice(), "bar");
+/// ```
+#[deriving(PartialEq, Clone, Show)]
+pub struct CookieRef<'a> {
+    pub name: CowString<'a>,
+    pub value: CowString<'a>,
+    pub quoted: bool,
+    pub expires: Option<time::Tm>,
+    pub max_age: Option<i64>,
+    pub domain: Option<&'a str>,
+    pub path: Option<&'a str>,
+    pub secure: bool,
+    pub httponly: bool,
+    pub same_site: Option<SameSite>,
+    pub partitioned: bool,
+    pub priority: Option<Priority>,
+    pub custom: Vec<(CowString<'a>, CowString<'a>)>,
+}
+
+impl<'a> CookieRef<'a> {
+    /// Parses a `Set-Cookie` header value without copying it.
+    pub fn parse(s: &'a str) -> Result<CookieRef<'a>, ParseError> {
+        parse::strict(s)
+    }
+
+    /// Copies this cookie into an owned `Cookie`.
+    ///
+    /// Custom attributes which appear more than once keep their last value.
+    pub fn into_owned(self) -> Cookie {
+        let mut c = Cookie::new(self.name.into_owned(), self.value.into_owned());
+        c.quoted = self.quoted;
+        c.expires = self.expires;
+        c.max_age = self.max_age;
+        c.domain = self.domain.map(|s| s.to_string());
+        c.path = self.path.map(|s| s.to_string());
+        c.secure = self.secure;
+        c.httponly = self.httponly;
+        c.same_site = self.same_site;
+        c.partitioned = self.partitioned;
+        c.priority = self.priority;
+        for (k, v) in self.custom.into_iter() {
+            c.custom.insert(k.into_owned(), v.into_owned());
+        }
+        c
     }
 }
 
@@ -96,22 +299,50 @@ pub struct AttrVal<'a>(pub &'a str, pub &'a str);
 impl<'a> fmt::Show for AttrVal<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let AttrVal(ref attr, ref val) = *self;
-        write!(f, "{}={}", attr, url::percent_encode(val.as_bytes(),
-                                                     url::DEFAULT_ENCODE_SET))
+        write!(f, "{}={}", header::escape(*attr, is_name_char), encode_value(*val))
+    }
+}
+
+/// A cookie's `name=value` pair, with the value in double quotes if the
+/// cookie is `quoted`.
+pub struct Pair<'a>(&'a Cookie);
+
+impl<'a> fmt::Show for Pair<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Pair(c) = *self;
+        let quote = if c.quoted { "\"" } else { "" };
+        write!(f, "{}={}{}{}", header::escape(c.name.as_slice(), is_name_char),
+               quote, encode_value(c.value.as_slice()), quote)
     }
 }
 
+// Both the name and value are percent-decoded when parsed, so a literal `%`
+// has to be escaped too. The default encode set leaves it alone, as well as
+// `;`, `,` and `\`, none of which are allowed in a cookie value. It does
+// encode `"`, so a quoted value can't end early.
+fn encode_value(val: &str) -> String {
+    let val = url::percent_encode(val.replace("%", "%25").as_bytes(),
+                                  url::DEFAULT_ENCODE_SET);
+    val.replace(";", "%3B").replace(",", "%2C").replace("\\", "%5C")
+}
+
 impl fmt::Show for Cookie {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        try!(AttrVal(self.name.as_slice(), self.value.as_slice()).fmt(f));
+        try!(self.pair().fmt(f));
         if self.httponly { try!(write!(f, "; HttpOnly")); }
         if self.secure { try!(write!(f, "; Secure")); }
         match self.path {
-            Some(ref s) => try!(write!(f, "; Path={}", s)),
+            Some(ref s) => {
+                try!(write!(f, "; Path={}", header::escape(s.as_slice(),
+                                                           header::is_path_char)));
+            }
             None => {}
         }
         match self.domain {
-            Some(ref s) => try!(write!(f, "; Domain={}", s)),
+            Some(ref s) => {
+                try!(write!(f, "; Domain={}", header::escape(s.as_slice(),
+                                                             header::is_domain_char)));
+            }
             None => {}
         }
         match self.max_age {
@@ -119,7 +350,50 @@ impl fmt::Show for Cookie {
             None => {}
         }
         match self.expires {
-            Some(ref t) => try!(write!(f, "; Expires={}", t.rfc822())),
+            Some(ref t) => {
+                try!(write!(f, "; Expires={}", date::format_cookie_date(t)));
+            }
+            None => {}
+        }
+        match self.same_site {
+            Some(s) => try!(write!(f, "; SameSite={}", s)),
+            None => {}
+        }
+        if self.partitioned { try!(write!(f, "; Partitioned")); }
+        match self.priority {
+            Some(p) => try!(write!(f, "; Priority={}", p)),
+            None => {}
+        }
+
+        match self.legacy {
+            Some(ref l) => {
+                match l.version {
+                    Some(v) => try!(write!(f, "; Version={}", v)),
+                    None => {}
+                }
+                match l.comment {
+                    Some(ref s) => {
+                        try!(write!(f, "; Comment={}", header::quote(s.as_slice())));
+                    }
+                    None => {}
+                }
+                match l.comment_url {
+                    Some(ref s) => {
+                        try!(write!(f, "; CommentURL={}", header::quote(s.as_slice())));
+                    }
+                    None => {}
+                }
+                if l.discard { try!(write!(f, "; Discard")); }
+                match l.port {
+                    Some(ref ports) if ports.is_empty() => try!(write!(f, "; Port")),
+                    Some(ref ports) => {
+                        let ports = ports.iter().map(|p| p.to_string())
+                                         .collect::<Vec<_>>();
+                        try!(write!(f, "; Port=\"{}\"", ports.connect(",")));
+                    }
+                    None => {}
+                }
+            }
             None => {}
         }
 
@@ -130,15 +404,343 @@ impl fmt::Show for Cookie {
     }
 }
 
+// A token character which won't be mistaken for a percent-encoding.
+fn is_name_char(ch: char) -> bool {
+    is_token_char(ch) && ch != '%'
+}
+
+/// Returns whether `name` starts with `prefix`, ignoring ASCII case.
+fn has_prefix(name: &str, prefix: &str) -> bool {
+    name.len() >= prefix.len() &&
+        name.as_bytes().slice_to(prefix.len()).iter()
+            .zip(prefix.as_bytes().iter())
+            .all(|(a, b)| (*a as char).to_lowercase() == (*b as char).to_lowercase())
+}
+
 impl FromStr for Cookie {
     fn from_str(s: &str) -> Option<Cookie> {
         Cookie::parse(s).ok()
     }
 }
 
+/// The longest a browser will keep a cookie, in seconds: 400 days.
+pub const MAX_EXPIRY_SECS: i64 = 400 * 24 * 60 * 60;
+
+/// When a cookie expires, as resolved by `Cookie::expiration`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum Expiration {
+    /// The cookie is discarded when the browsing session ends.
+    Session,
+    /// The cookie is discarded at the given instant.
+    At(time::Timespec),
+}
+
+/// The value of a cookie's `SameSite` attribute.
+///
+/// Parsing is case-insensitive. An unrecognized value leaves the cookie
+/// without a `SameSite` attribute, as RFC 6265bis specifies.
+#[deriving(PartialEq, Eq, Clone, Copy, Show)]
+pub enum SameSite {
+    /// The cookie is only sent with same-site requests.
+    Strict,
+    /// The cookie is also sent with top-level cross-site navigations.
+    Lax,
+    /// The cookie is sent with cross-site requests. Browsers require such
+    /// cookies to also be `Secure`.
+    None,
+}
+
+impl FromStr for SameSite {
+    fn from_str(s: &str) -> Option<SameSite> {
+        if s.eq_ignore_ascii_case("Strict") {
+            Some(SameSite::Strict)
+        } else if s.eq_ignore_ascii_case("Lax") {
+            Some(SameSite::Lax)
+        } else if s.eq_ignore_ascii_case("None") {
+            Some(SameSite::None)
+        } else {
+            None
+        }
+    }
+}
+
+/// The value of a cookie's `Priority` attribute.
+///
+/// Parsing is case-insensitive, and an unrecognized value leaves the cookie
+/// without a `Priority` attribute.
+#[deriving(PartialEq, Eq, Clone, Copy, Show)]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+}
+
+impl FromStr for Priority {
+    fn from_str(s: &str) -> Option<Priority> {
+        if s.eq_ignore_ascii_case("Low") {
+            Some(Priority::Low)
+        } else if s.eq_ignore_ascii_case("Medium") {
+            Some(Priority::Medium)
+        } else if s.eq_ignore_ascii_case("High") {
+            Some(Priority::High)
+        } else {
+            None
+        }
+    }
+}
+
+/// The attributes of an RFC 2965 `Set-Cookie2` cookie which RFC 6265 has
+/// no equivalent for.
+///
+/// These are only set by `ParseMode::Legacy`. `Cookie::into_modern` drops
+/// them.
+#[deriving(PartialEq, Eq, Clone, Default, Show)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+pub struct Legacy {
+    /// The `Version` attribute, which RFC 2965 requires to be 1.
+    pub version: Option<u32>,
+    pub comment: Option<String>,
+    pub comment_url: Option<String>,
+    /// Whether `Discard` was set, making this a session cookie whatever its
+    /// `Max-Age`.
+    pub discard: bool,
+    /// The ports the cookie may be returned to. An empty list is a bare
+    /// `Port`, which restricts the cookie to the port of the request which
+    /// set it.
+    pub port: Option<Vec<u16>>,
+}
+
+/// The rules used by `Cookie::parse_with` to interpret a `Set-Cookie` header.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum ParseMode {
+    /// Attribute names are matched case-sensitively and any malformed
+    /// attribute causes the whole cookie to be rejected.
+    Strict,
+    /// The user agent algorithm of RFC 6265 section 5.2, as implemented by
+    /// browsers. Attribute names are case-insensitive and attributes with
+    /// invalid values are ignored rather than rejecting the cookie. The
+    /// attributes aren't checked against each other, so use
+    /// `Cookie::validate` to reject `Partitioned` without `Secure`.
+    ///
+    /// The RFC's default-path depends on the request URI, so a cookie without
+    /// a valid `Path` attribute is parsed with a `path` of `None`.
+    Lenient,
+    /// The `Set-Cookie2` syntax of RFC 2965, as sent by some old servers.
+    ///
+    /// This is `Lenient` with the addition of quoted-string values, which
+    /// may contain `;`, and the `Version`, `Comment`, `CommentURL`, `Discard`
+    /// and `Port` attributes, which are kept in the cookie's `legacy` field.
+    /// A header listing several cookies must be split before parsing.
+    Legacy,
+}
+
+/// An error which can occur when parsing a cookie.
+///
+/// Every variant records the byte offset into the original string at which
+/// the problem was found, along with the offending piece of input.
+#[deriving(PartialEq, Eq, Clone)]
+pub enum ParseError {
+    /// The leading `name=value` pair had no `=`.
+    MissingPair { offset: uint, input: String },
+    /// The cookie's name was empty.
+    EmptyName { offset: uint, input: String },
+    /// An attribute other than `Secure` or `HttpOnly` had no `=`.
+    MissingValue { attr: String, offset: uint, input: String },
+    /// The value of `Max-Age` was not a number of seconds.
+    InvalidMaxAge { offset: uint, input: String },
+    /// The value of `Expires` was not a recognized date.
+    InvalidExpires { offset: uint, input: String },
+    /// The name or value was not valid UTF-8 once percent-decoded.
+    InvalidUtf8 { attr: String, offset: uint, input: String },
+    /// The cookie parsed but failed validation. The offset is always 0 and
+    /// the input is the whole cookie.
+    Invalid { error: ValidationError, input: String },
+}
+
+impl ParseError {
+    /// Returns the name of the attribute which failed to parse, if any.
+    ///
+    /// For errors in the leading pair this is either `"name"` or `"value"`.
+    pub fn attr(&self) -> Option<&str> {
+        match *self {
+            ParseError::MissingPair { .. } => None,
+            ParseError::EmptyName { .. } => Some("name"),
+            ParseError::MissingValue { ref attr, .. } => Some(attr.as_slice()),
+            ParseError::InvalidMaxAge { .. } => Some("Max-Age"),
+            ParseError::InvalidExpires { .. } => Some("Expires"),
+            ParseError::InvalidUtf8 { ref attr, .. } => Some(attr.as_slice()),
+            ParseError::Invalid { ref error, .. } => Some(error.attr()),
+        }
+    }
+
+    /// Returns the byte offset in the original string of the offending input.
+    pub fn offset(&self) -> uint {
+        match *self {
+            ParseError::MissingPair { offset, .. } |
+            ParseError::EmptyName { offset, .. } |
+            ParseError::MissingValue { offset, .. } |
+            ParseError::InvalidMaxAge { offset, .. } |
+            ParseError::InvalidExpires { offset, .. } |
+            ParseError::InvalidUtf8 { offset, .. } => offset,
+            ParseError::Invalid { .. } => 0,
+        }
+    }
+
+    /// Returns the piece of input which could not be parsed.
+    pub fn input(&self) -> &str {
+        match *self {
+            ParseError::MissingPair { ref input, .. } |
+            ParseError::EmptyName { ref input, .. } |
+            ParseError::MissingValue { ref input, .. } |
+            ParseError::InvalidMaxAge { ref input, .. } |
+            ParseError::InvalidExpires { ref input, .. } |
+            ParseError::InvalidUtf8 { ref input, .. } |
+            ParseError::Invalid { ref input, .. } => input.as_slice(),
+        }
+    }
+}
+
+impl fmt::Show for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.description()));
+        match self.attr() {
+            Some(attr) => try!(write!(f, " in `{}`", attr)),
+            None => {}
+        }
+        write!(f, " at byte {}: `{}`", self.offset(), self.input())
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        match *self {
+            ParseError::MissingPair { .. } => "missing `=` in cookie pair",
+            ParseError::EmptyName { .. } => "empty cookie name",
+            ParseError::MissingValue { .. } => "missing `=` in attribute",
+            ParseError::InvalidMaxAge { .. } => "invalid Max-Age",
+            ParseError::InvalidExpires { .. } => "invalid Expires date",
+            ParseError::InvalidUtf8 { .. } => "invalid UTF-8 after percent-decoding",
+            ParseError::Invalid { ref error, .. } => error.description(),
+        }
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+/// A reason a cookie would be rejected by a browser even though it is
+/// syntactically valid.
+#[deriving(PartialEq, Eq, Clone)]
+pub enum ValidationError {
+    /// The `Partitioned` attribute was set without `Secure`.
+    PartitionedWithoutSecure,
+    /// The name starts with `__Secure-` but `Secure` wasn't set.
+    SecurePrefixWithoutSecure,
+    /// The name starts with `__Host-` but `Secure` wasn't set.
+    HostPrefixWithoutSecure,
+    /// The name starts with `__Host-` but a `Domain` was set.
+    HostPrefixWithDomain,
+    /// The name starts with `__Host-` but the `Path` wasn't `/`.
+    HostPrefixWithoutRootPath,
+    /// The name was empty.
+    EmptyName,
+    /// The name contained a character which isn't allowed in a token.
+    InvalidNameChar(char),
+    /// The value contained a control character.
+    InvalidValueChar(char),
+    /// The name and value together were too long. The total length in bytes
+    /// is given.
+    TooLarge(uint),
+    /// The named attribute's value was too long.
+    AttrTooLarge(String),
+    /// The named attribute contained a character which isn't allowed.
+    InvalidAttr(String),
+}
+
+impl ValidationError {
+    /// Returns the name of the attribute which is invalid.
+    ///
+    /// Problems with the name or value give `"name"` or `"value"`.
+    pub fn attr(&self) -> &str {
+        match *self {
+            ValidationError::PartitionedWithoutSecure => "Partitioned",
+            ValidationError::SecurePrefixWithoutSecure |
+            ValidationError::HostPrefixWithoutSecure => "Secure",
+            ValidationError::HostPrefixWithDomain => "Domain",
+            ValidationError::HostPrefixWithoutRootPath => "Path",
+            ValidationError::EmptyName |
+            ValidationError::InvalidNameChar(..) => "name",
+            ValidationError::InvalidValueChar(..) |
+            ValidationError::TooLarge(..) => "value",
+            ValidationError::AttrTooLarge(ref attr) |
+            ValidationError::InvalidAttr(ref attr) => attr.as_slice(),
+        }
+    }
+}
+
+impl fmt::Show for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::InvalidNameChar(ch) |
+            ValidationError::InvalidValueChar(ch) => {
+                write!(f, "{} (U+{:04X})", self.description(), ch as u32)
+            }
+            ValidationError::TooLarge(n) => {
+                write!(f, "{} ({} bytes)", self.description(), n)
+            }
+            ValidationError::AttrTooLarge(ref attr) |
+            ValidationError::InvalidAttr(ref attr) => {
+                write!(f, "{}: `{}`", self.description(), attr)
+            }
+            _ => write!(f, "{}", self.description()),
+        }
+    }
+}
+
+impl Error for ValidationError {
+    fn description(&self) -> &str {
+        match *self {
+            ValidationError::PartitionedWithoutSecure => {
+                "the Partitioned attribute requires Secure"
+            }
+            ValidationError::SecurePrefixWithoutSecure => {
+                "a cookie named with the __Secure- prefix requires Secure"
+            }
+            ValidationError::HostPrefixWithoutSecure => {
+                "a cookie named with the __Host- prefix requires Secure"
+            }
+            ValidationError::HostPrefixWithDomain => {
+                "a cookie named with the __Host- prefix can't have a Domain"
+            }
+            ValidationError::HostPrefixWithoutRootPath => {
+                "a cookie named with the __Host- prefix requires Path=/"
+            }
+            ValidationError::EmptyName => "the cookie name is empty",
+            ValidationError::InvalidNameChar(..) => {
+                "the cookie name contains an invalid character"
+            }
+            ValidationError::InvalidValueChar(..) => {
+                "the cookie value contains a control character"
+            }
+            ValidationError::TooLarge(..) => "the cookie name and value are too long",
+            ValidationError::AttrTooLarge(..) => "an attribute value is too long",
+            ValidationError::InvalidAttr(..) => {
+                "an attribute contains an invalid character"
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Cookie;
+    use std::borrow::Cow;
+    use std::default::Default;
+    use super::{Cookie, CookieRef, ParseError, ParseMode, SameSite, Priority, Legacy};
+    use super::{ValidationError, Expiration, MAX_EXPIRY_SECS};
+    use super::{Limits, MAX_NAME_VALUE_LEN};
+    use time;
+    use time::Timespec;
 
     #[test]
     fn parse() {
@@ -169,15 +771,320 @@ mod tests {
                     Max-Age=4; wut=lol");
     }
 
+    #[test]
+    fn same_site() {
+        let c = Cookie::parse("foo=bar; SameSite=Lax").unwrap();
+        assert_eq!(c.same_site, Some(SameSite::Lax));
+        assert_eq!(c.to_string().as_slice(), "foo=bar; Path=/; SameSite=Lax");
+
+        let c = Cookie::parse("foo=bar; samesite=STRICT").unwrap();
+        assert_eq!(c.same_site, Some(SameSite::Strict));
+        assert!(c.custom.is_empty());
+
+        let c = Cookie::parse("foo=bar; SameSite=None; Secure").unwrap();
+        assert_eq!(c.same_site, Some(SameSite::None));
+        assert_eq!(c.to_string().as_slice(),
+                   "foo=bar; Secure; Path=/; SameSite=None");
+
+        let c = Cookie::parse("foo=bar; SameSite=Lax; SameSite=bogus").unwrap();
+        assert_eq!(c.same_site, None);
+        assert!(c.custom.is_empty());
+    }
+
+    #[test]
+    fn limits() {
+        let s = format!("a={}", String::from_char(MAX_NAME_VALUE_LEN, 'b'));
+        let too_large = ValidationError::TooLarge(MAX_NAME_VALUE_LEN + 1);
+        assert_eq!(Cookie::parse(s.as_slice()),
+                   Err(ParseError::Invalid { error: too_large, input: s.clone() }));
+        assert!(Cookie::parse_with(s.as_slice(), ParseMode::Lenient).is_err());
+        let limits = Limits { max_name_value_len: 8192, ..Default::default() };
+        assert!(Cookie::parse_with_limits(s.as_slice(), ParseMode::Strict, &limits).is_ok());
+
+        let s = format!("a=b; Path=/{}", String::from_char(1024, 'c'));
+        assert_eq!(Cookie::parse_with(s.as_slice(), ParseMode::Lenient),
+                   Err(ParseError::Invalid {
+                       error: ValidationError::AttrTooLarge("Path".to_string()),
+                       input: s.clone(),
+                   }));
+
+        // Browsers don't decode values, so only raw control characters count.
+        assert_eq!(Cookie::parse("a=b%0D%0Ac").unwrap().value.as_slice(), "b\r\nc");
+        assert_eq!(Cookie::parse_with("a=b\x01c", ParseMode::Lenient),
+                   Err(ParseError::Invalid {
+                       error: ValidationError::InvalidValueChar('\x01'),
+                       input: "a=b\x01c".to_string(),
+                   }));
+        assert_eq!(Cookie::parse_with("a=b; x=\x7f", ParseMode::Lenient),
+                   Err(ParseError::Invalid {
+                       error: ValidationError::InvalidAttr("x".to_string()),
+                       input: "a=b; x=\x7f".to_string(),
+                   }));
+
+        // Cookies are measured as they are written.
+        let c = Cookie::new("a".to_string(), String::from_char(2000, ' '));
+        assert_eq!(Limits::default().check(&c), Err(ValidationError::TooLarge(6001)));
+    }
+
+    #[test]
+    fn legacy() {
+        let s = "Customer=\"WILE_E; COYOTE\"; Version=\"1\"; Path=\"/acme\"; \
+                 Comment=\"say \\\"hi\\\"\"; CommentURL=\"http://a.example/\"; \
+                 Discard; Port=\"80,8080\"; Max-Age=60";
+        assert!(Cookie::parse(s).is_err());
+
+        let c = Cookie::parse_with(s, ParseMode::Legacy).unwrap();
+        assert_eq!(c.value.as_slice(), "WILE_E; COYOTE");
+        assert_eq!(c.path, Some("/acme".to_string()));
+        assert_eq!(c.max_age, Some(60));
+        let legacy = Legacy {
+            version: Some(1),
+            comment: Some("say \"hi\"".to_string()),
+            comment_url: Some("http://a.example/".to_string()),
+            discard: true,
+            port: Some(vec![80, 8080]),
+        };
+        assert_eq!(c.legacy, Some(legacy));
+        assert_eq!(c.to_string().as_slice(),
+                   "Customer=\"WILE_E%3B%20COYOTE\"; Path=/acme; Max-Age=60; \
+                    Version=1; Comment=\"say \\\"hi\\\"\"; \
+                    CommentURL=\"http://a.example/\"; Discard; Port=\"80,8080\"");
+        assert_eq!(Cookie::parse_with(c.to_string().as_slice(), ParseMode::Legacy),
+                   Ok(c.clone()));
+
+        // Discard makes it a session cookie.
+        let now = Timespec::new(1000000000, 0);
+        assert_eq!(c.expiration(now), Expiration::Session);
+        let modern = c.into_modern();
+        assert_eq!(modern.legacy, None);
+        assert_eq!(modern.max_age, None);
+        assert_eq!(modern.to_string().as_slice(),
+                   "Customer=\"WILE_E%3B%20COYOTE\"; Path=/acme");
+
+        // A `;` in a comment can't add an attribute for RFC 6265 user agents.
+        let c = Cookie::parse_with("a=b; Comment=\"x; Domain=evil.example\"",
+                                   ParseMode::Legacy).unwrap();
+        assert_eq!(c.legacy.as_ref().unwrap().comment,
+                   Some("x; Domain=evil.example".to_string()));
+        assert_eq!(c.render().unwrap().as_slice(),
+                   "a=b; Comment=\"x%3B Domain=evil.example\"");
+        assert!(!c.render().unwrap().as_slice().contains("; Domain"));
+        assert_eq!(Cookie::parse_with(c.to_string().as_slice(), ParseMode::Legacy),
+                   Ok(c.clone()));
+
+        let c = Cookie::parse_with("a=b; Version=1; Port", ParseMode::Legacy).unwrap();
+        assert_eq!(c.legacy.as_ref().unwrap().port, Some(vec![]));
+        assert!(c.to_string().as_slice().ends_with("; Version=1; Port"));
+    }
+
+    #[test]
+    fn partitioned_and_priority() {
+        let c = Cookie::parse("foo=bar; Secure; Partitioned; Priority=High")
+                      .unwrap();
+        assert!(c.partitioned);
+        assert_eq!(c.priority, Some(Priority::High));
+        assert_eq!(c.to_string().as_slice(),
+                   "foo=bar; Secure; Path=/; Partitioned; Priority=High");
+
+        let c = Cookie::parse("foo=bar; priority=low; Priority=urgent").unwrap();
+        assert_eq!(c.priority, None);
+        assert!(c.custom.is_empty());
+
+        let err = Cookie::parse("foo=bar; Partitioned").unwrap_err();
+        assert_eq!(err, ParseError::Invalid {
+            error: ValidationError::PartitionedWithoutSecure,
+            input: "foo=bar; Partitioned".to_string(),
+        });
+        assert_eq!(err.attr(), Some("Partitioned"));
+
+        let c = Cookie::parse_with("foo=bar; Partitioned", ParseMode::Lenient).unwrap();
+        assert!(c.partitioned);
+        assert_eq!(c.validate(), Err(ValidationError::PartitionedWithoutSecure));
+
+        let mut c = Cookie::new("foo".to_string(), "bar".to_string());
+        c.partitioned = true;
+        assert_eq!(c.validate(), Err(ValidationError::PartitionedWithoutSecure));
+        c.secure = true;
+        assert_eq!(c.validate(), Ok(()));
+    }
+
+    #[test]
+    fn prefixes() {
+        let mut c = Cookie::new("__Secure-id".to_string(), "1".to_string());
+        assert_eq!(c.validate_prefix(), Err(ValidationError::SecurePrefixWithoutSecure));
+        c.secure = true;
+        assert_eq!(c.validate_prefix(), Ok(()));
+        c.domain = Some("foo.com".to_string());
+        c.path = None;
+        assert_eq!(c.validate_prefix(), Ok(()));
+
+        let mut c = Cookie::new("__host-id".to_string(), "1".to_string());
+        assert_eq!(c.validate_prefix(), Err(ValidationError::HostPrefixWithoutSecure));
+        c.secure = true;
+        assert_eq!(c.validate_prefix(), Ok(()));
+        c.path = Some("/foo".to_string());
+        assert_eq!(c.validate_prefix(), Err(ValidationError::HostPrefixWithoutRootPath));
+        c.path = Some("/".to_string());
+        c.domain = Some("foo.com".to_string());
+        assert_eq!(c.validate_prefix(), Err(ValidationError::HostPrefixWithDomain));
+
+        let c = Cookie::new("__Hostile".to_string(), "1".to_string());
+        assert_eq!(c.validate_prefix(), Ok(()));
+
+        // Parsing leaves the prefix to the caller.
+        let c = Cookie::parse("__Host-id=1; Secure; Domain=foo.com").unwrap();
+        assert_eq!(c.validate(), Ok(()));
+        assert_eq!(c.validate_prefix(), Err(ValidationError::HostPrefixWithDomain));
+    }
+
+    #[test]
+    fn expires_round_trip() {
+        let s = "foo=bar; Path=/; Expires=Sun, 06 Nov 1994 08:49:37 GMT";
+        let c = Cookie::parse(s).unwrap();
+        assert_eq!(c.to_string().as_slice(), s);
+
+        let c = Cookie::parse("foo=bar; Expires=Sunday, 06-Nov-94 08:49:37 GMT")
+                      .unwrap();
+        assert_eq!(c.to_string().as_slice(), s);
+
+        // The same instant, in UTC+01:00.
+        let mut c = Cookie::new("foo".to_string(), "bar".to_string());
+        let mut tm = time::at_utc(Timespec::new(784111777 + 3600, 0));
+        tm.tm_utcoff = 3600;
+        c.expires = Some(tm);
+        assert_eq!(c.to_string().as_slice(), s);
+        let now = Timespec::new(784111000, 0);
+        assert_eq!(c.expiration(now),
+                   Expiration::At(Timespec::new(784111777, 0)));
+    }
+
+    #[test]
+    fn max_age_sign() {
+        assert_eq!(Cookie::parse("foo=bar; Max-Age=-1").unwrap().max_age, Some(-1));
+        assert!(Cookie::parse("foo=bar; Max-Age=1.5").is_err());
+    }
+
+    #[test]
+    fn expiration() {
+        let now = Timespec::new(1000000000, 0);
+        let mut c = Cookie::new("foo".to_string(), "bar".to_string());
+        assert_eq!(c.expiration(now), Expiration::Session);
+        assert!(!c.is_expired(now));
+
+        c.expires = Some(time::at_utc(Timespec::new(1000000060, 0)));
+        assert_eq!(c.expiration(now), Expiration::At(Timespec::new(1000000060, 0)));
+        assert!(!c.is_expired(now));
+
+        // Max-Age wins over Expires.
+        c.max_age = Some(30);
+        assert_eq!(c.expiration(now), Expiration::At(Timespec::new(1000000030, 0)));
+
+        c.max_age = Some(0);
+        assert!(c.is_expired(now));
+        c.max_age = Some(-1);
+        assert!(c.is_expired(now));
+
+        // Nothing lasts longer than 400 days.
+        let latest = Expiration::At(Timespec::new(1000000000 + MAX_EXPIRY_SECS, 0));
+        c.max_age = Some(::std::i64::MAX);
+        assert_eq!(c.expiration(now), latest);
+        c.max_age = None;
+        c.expires = Some(time::at_utc(Timespec::new(2000000000, 0)));
+        assert_eq!(c.expiration(now), latest);
+        c.expires = Some(time::at_utc(Timespec::new(999999999, 0)));
+        assert!(c.is_expired(now));
+    }
+
     #[test]
     fn odd_characters() {
         let expected = Cookie::new("foo".to_string(), "b/r".to_string());
         assert_eq!(Cookie::parse("foo=b%2Fr").unwrap(), expected);
     }
 
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Cookie::parse(" foo; Secure"),
+                   Err(ParseError::MissingPair {
+                       offset: 1,
+                       input: "foo".to_string(),
+                   }));
+        assert_eq!(Cookie::parse("foo=bar; Path"),
+                   Err(ParseError::MissingValue {
+                       attr: "Path".to_string(),
+                       offset: 9,
+                       input: "Path".to_string(),
+                   }));
+
+        let err = Cookie::parse("foo=bar; Max-Age=abc").unwrap_err();
+        assert_eq!(err.attr(), Some("Max-Age"));
+        assert_eq!(err.offset(), 17);
+        assert_eq!(err.input(), "abc");
+
+        let err = Cookie::parse("foo=bar; Expires=yesterday").unwrap_err();
+        assert_eq!(err.attr(), Some("Expires"));
+        assert_eq!(err.offset(), 17);
+        assert_eq!(err.input(), "yesterday");
+
+        let err = Cookie::parse("foo=b%FFr").unwrap_err();
+        assert_eq!(err.attr(), Some("value"));
+        assert_eq!(err.offset(), 4);
+        assert_eq!(err.input(), "b%FFr");
+        assert_eq!(err.to_string().as_slice(),
+                   "invalid UTF-8 after percent-decoding in `value` at byte 4: \
+                    `b%FFr`");
+    }
+
+    #[test]
+    fn borrowed() {
+        let s = "foo=bar; HttpOnly; Max-Age=4; Path=/foo; Domain=foo.com; a=b";
+        let c = CookieRef::parse(s).unwrap();
+        assert_eq!(c.name, Cow::Borrowed("foo"));
+        assert_eq!(c.value, Cow::Borrowed("bar"));
+        assert_eq!(c.domain, Some("foo.com"));
+        assert_eq!(c.custom, vec![(Cow::Borrowed("a"), Cow::Borrowed("b"))]);
+        assert_eq!(c.into_owned(), Cookie::parse(s).unwrap());
+
+        let c = CookieRef::parse("foo=b%2Fr").unwrap();
+        assert_eq!(c.value, Cow::Owned("b/r".to_string()));
+        assert_eq!(c.path, Some("/"));
+
+        assert_eq!(CookieRef::parse("foo=bar; Path"),
+                   Err(ParseError::MissingValue {
+                       attr: "Path".to_string(),
+                       offset: 9,
+                       input: "Path".to_string(),
+                   }));
+    }
+
     #[test]
     fn pair() {
         let cookie = Cookie::new("foo".to_string(), "bar".to_string());
         assert_eq!(cookie.pair().to_string(), "foo=bar".to_string());
+
+        let cookie = Cookie::new("foo".to_string(), "b;r".to_string());
+        assert_eq!(cookie.pair().to_string(), "foo=b%3Br".to_string());
+        assert_eq!(Cookie::parse(cookie.to_string().as_slice()).unwrap(), cookie);
+    }
+
+    #[test]
+    fn quoted() {
+        let c = Cookie::parse("foo=\"b r\"; Path=/").unwrap();
+        assert_eq!(c.value.as_slice(), "b r");
+        assert!(c.quoted);
+        assert_eq!(c.pair().to_string().as_slice(), "foo=\"b%20r\"");
+        assert_eq!(c.to_string().as_slice(), "foo=\"b%20r\"; Path=/");
+        assert_eq!(Cookie::parse(c.to_string().as_slice()).unwrap(), c);
+
+        let c = CookieRef::parse("foo=\"\"").unwrap();
+        assert_eq!(c.value, Cow::Borrowed(""));
+        assert!(c.quoted);
+
+        // Only a value which is entirely wrapped in quotes is quoted.
+        for s in ["foo=\"", "foo=\"bar", "foo=b\"a\"r"].iter() {
+            let c = Cookie::parse(*s).unwrap();
+            assert!(!c.quoted);
+            assert_eq!(c.value.as_slice(), s.slice_from(4));
+            assert!(!c.to_string().as_slice().contains("\""));
+        }
     }
 }
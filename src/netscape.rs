@@ -0,0 +1,399 @@
+//! Reading and writing the Netscape `cookies.txt` format used by curl, wget
+//! and many browser extensions.
+//!
+//! Each cookie is a line of seven tab-separated fields: the domain, whether
+//! subdomains are included, the path, whether the cookie is secure, the
+//! expiry in seconds since the Unix epoch, the name and the value. An expiry
+//! of 0 marks a session cookie. Lines starting with `#` are comments, except
+//! that curl writes HttpOnly cookies with their domain prefixed by
+//! `#HttpOnly_`.
+
+use std::ascii::AsciiExt;
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use time;
+use time::Timespec;
+
+use {Cookie, Expiration};
+use date;
+use header;
+use parse::{decode_or_raw, unwrap_quotes};
+
+const HTTP_ONLY_PREFIX: &'static str = "#HttpOnly_";
+
+/// The contents of a `cookies.txt` file.
+///
+/// Comments and blank lines are kept in place, so a file which is read and
+/// written again only changes where cookies were added.
+///
+/// # Example
+///
+/// ```
+/// use cookie::{CookiesTxt, CookieJar};
+///
+/// let txt = CookiesTxt::parse("# Netscape HTTP Cookie File\n\
+///                              .example.com\tTRUE\t/\tFALSE\t0\tid\t42\n")
+///                      .unwrap();
+///
+/// let mut jar = CookieJar::new(b"f8f9eaf1ecdedff5e5b749c58115441e");
+/// for cookie in txt.cookies().into_iter() {
+///     jar.add_original(cookie);
+/// }
+/// assert_eq!(jar.find("id").unwrap().value.as_slice(), "42");
+/// ```
+#[deriving(PartialEq, Clone)]
+pub struct CookiesTxt {
+    lines: Vec<Line>,
+}
+
+#[deriving(PartialEq, Clone)]
+enum Line {
+    // A comment or blank line, as written.
+    Text(String),
+    Entry(CookiesTxtEntry),
+}
+
+/// One cookie in a `cookies.txt` file.
+///
+/// The cookie's domain is always set, without any leading `.`, and its
+/// expiry is in `expires`. A cookie is a session cookie if `expires` is
+/// `None`.
+#[deriving(PartialEq, Clone)]
+pub struct CookiesTxtEntry {
+    pub cookie: Cookie,
+    pub include_subdomains: bool,
+}
+
+impl CookiesTxt {
+    /// Creates a file containing only the usual header comment.
+    pub fn new() -> CookiesTxt {
+        CookiesTxt {
+            lines: vec![Line::Text("# Netscape HTTP Cookie File".to_string())],
+        }
+    }
+
+    /// Parses the contents of a `cookies.txt` file.
+    pub fn parse(s: &str) -> Result<CookiesTxt, CookiesTxtError> {
+        let mut lines = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim_right_chars('\r');
+            if line.starts_with(HTTP_ONLY_PREFIX) {
+                let rest = line.slice_from(HTTP_ONLY_PREFIX.len());
+                let mut entry = try!(parse_entry(rest, i + 1));
+                entry.cookie.httponly = true;
+                lines.push(Line::Entry(entry));
+            } else if line.starts_with("#") || line.trim().is_empty() {
+                lines.push(Line::Text(line.to_string()));
+            } else {
+                lines.push(Line::Entry(try!(parse_entry(line, i + 1))));
+            }
+        }
+        Ok(CookiesTxt { lines: lines })
+    }
+
+    /// Returns the entries in the file, in order.
+    pub fn entries(&self) -> Vec<&CookiesTxtEntry> {
+        self.lines.iter().filter_map(|line| {
+            match *line {
+                Line::Entry(ref e) => Some(e),
+                Line::Text(..) => None,
+            }
+        }).collect()
+    }
+
+    /// Returns copies of the cookies in the file, in order.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        self.entries().into_iter().map(|e| e.cookie.clone()).collect()
+    }
+
+    /// Appends a cookie to the file.
+    ///
+    /// The format has no `Max-Age`, so the cookie's expiry is resolved as of
+    /// the current time. A cookie without a domain is written with an empty
+    /// one.
+    pub fn push(&mut self, mut cookie: Cookie, include_subdomains: bool) {
+        cookie.expires = match cookie.expiration(time::get_time()) {
+            Expiration::Session => None,
+            // An expiry of 0 would turn an expired cookie into a session one.
+            Expiration::At(t) => {
+                Some(time::at_utc(Timespec::new(cmp::max(t.sec, 1), 0)))
+            }
+        };
+        cookie.max_age = None;
+        cookie.domain = cookie.domain.map(|d| {
+            if d.as_slice().starts_with(".") {
+                d.as_slice().slice_from(1).to_string()
+            } else {
+                d
+            }
+        });
+        self.lines.push(Line::Entry(CookiesTxtEntry {
+            cookie: cookie,
+            include_subdomains: include_subdomains,
+        }));
+    }
+
+    /// Appends a comment line. A `# ` is added to the start of `text`.
+    pub fn push_comment(&mut self, text: &str) {
+        self.lines.push(Line::Text(format!("# {}", text)));
+    }
+}
+
+fn parse_entry(line: &str, lineno: uint) -> Result<CookiesTxtEntry, CookiesTxtError> {
+    let fields = line.split('\t').collect::<Vec<_>>();
+    if fields.len() != 7 {
+        return Err(CookiesTxtError {
+            line: lineno,
+            kind: CookiesTxtErrorKind::FieldCount(fields.len()),
+        })
+    }
+    let include_subdomains = try!(flag(fields[1], lineno));
+    let secure = try!(flag(fields[3], lineno));
+    let expiry: i64 = match from_str(fields[4]) {
+        Some(n) if n >= 0 => n,
+        _ => return Err(CookiesTxtError {
+            line: lineno,
+            kind: CookiesTxtErrorKind::InvalidExpiry(fields[4].to_string()),
+        }),
+    };
+
+    let (value, quoted) = unwrap_quotes(fields[6]);
+    let mut cookie = Cookie::new(decode_or_raw(fields[5]), decode_or_raw(value));
+    cookie.quoted = quoted;
+    let domain = fields[0];
+    let domain = if domain.starts_with(".") { domain.slice_from(1) } else { domain };
+    cookie.domain = Some(domain.to_string());
+    cookie.path = Some(fields[2].to_string());
+    cookie.secure = secure;
+    if expiry != 0 {
+        cookie.expires = Some(time::at_utc(Timespec::new(expiry, 0)));
+    }
+    return Ok(CookiesTxtEntry { cookie: cookie, include_subdomains: include_subdomains });
+
+    fn flag(s: &str, lineno: uint) -> Result<bool, CookiesTxtError> {
+        if s.eq_ignore_ascii_case("TRUE") {
+            Ok(true)
+        } else if s.eq_ignore_ascii_case("FALSE") {
+            Ok(false)
+        } else {
+            Err(CookiesTxtError {
+                line: lineno,
+                kind: CookiesTxtErrorKind::InvalidFlag(s.to_string()),
+            })
+        }
+    }
+}
+
+impl fmt::Show for CookiesTxtEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = &self.cookie;
+        if c.httponly { try!(write!(f, "{}", HTTP_ONLY_PREFIX)); }
+        // Tabs and line breaks would add fields or lines, so the domain is
+        // encoded as it is in a `Set-Cookie` header and control characters in
+        // the path are percent-encoded.
+        let domain = c.domain.as_ref().map(|s| header::escape(s.as_slice(),
+                                                              header::is_domain_char))
+                             .unwrap_or(String::new());
+        let path = c.path.as_ref().map(|s| header::escape(s.as_slice(), is_path_char))
+                         .unwrap_or("/".to_string());
+        let dot = if self.include_subdomains && !domain.is_empty() { "." } else { "" };
+        let expiry = c.expires.as_ref().map(|t| date::to_timespec(t).sec).unwrap_or(0);
+        // Values are percent-encoded just as they are in a `Set-Cookie`
+        // header, and an encoded name can't contain `=`.
+        let pair = c.pair().to_string();
+        let eq = pair.as_slice().find('=').unwrap();
+        return write!(f, "{}{}\t{}\t{}\t{}\t{}\t{}\t{}", dot, domain,
+               if self.include_subdomains { "TRUE" } else { "FALSE" },
+               path, if c.secure { "TRUE" } else { "FALSE" },
+               expiry, pair.as_slice().slice_to(eq),
+               pair.as_slice().slice_from(eq + 1));
+
+        fn is_path_char(ch: char) -> bool {
+            !ch.is_control()
+        }
+    }
+}
+
+impl fmt::Show for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Line::Text(ref s) => write!(f, "{}", s),
+            Line::Entry(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl fmt::Show for CookiesTxt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.lines.iter() {
+            try!(write!(f, "{}\n", line));
+        }
+        Ok(())
+    }
+}
+
+/// An error in a `cookies.txt` file, with the line number it was found on,
+/// starting at 1.
+#[deriving(PartialEq, Eq, Clone)]
+pub struct CookiesTxtError {
+    pub line: uint,
+    pub kind: CookiesTxtErrorKind,
+}
+
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum CookiesTxtErrorKind {
+    /// The line had the given number of fields instead of 7.
+    FieldCount(uint),
+    /// The subdomain or secure field was neither `TRUE` nor `FALSE`.
+    InvalidFlag(String),
+    /// The expiry wasn't a non-negative number.
+    InvalidExpiry(String),
+}
+
+impl fmt::Show for CookiesTxtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "line {}: {}", self.line, self.description()));
+        match self.kind {
+            CookiesTxtErrorKind::FieldCount(n) => write!(f, " (found {})", n),
+            CookiesTxtErrorKind::InvalidFlag(ref s) |
+            CookiesTxtErrorKind::InvalidExpiry(ref s) => write!(f, ": `{}`", s),
+        }
+    }
+}
+
+impl Error for CookiesTxtError {
+    fn description(&self) -> &str {
+        match self.kind {
+            CookiesTxtErrorKind::FieldCount(..) => "expected 7 tab-separated fields",
+            CookiesTxtErrorKind::InvalidFlag(..) => "expected TRUE or FALSE",
+            CookiesTxtErrorKind::InvalidExpiry(..) => "invalid expiry time",
+        }
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time;
+    use time::Timespec;
+
+    use Cookie;
+    use super::{CookiesTxt, CookiesTxtError, CookiesTxtErrorKind};
+
+    const FILE: &'static str = "# Netscape HTTP Cookie File\n\
+                                # This is a generated file!\n\
+                                \n\
+                                .example.com\tTRUE\t/\tFALSE\t0\tsession\ta%20b\n\
+                                #HttpOnly_www.example.com\tFALSE\t/app\tTRUE\t\
+                                2000000000\tid\t42\n";
+
+    #[test]
+    fn read() {
+        let txt = CookiesTxt::parse(FILE).unwrap();
+        let entries = txt.entries();
+        assert_eq!(entries.len(), 2);
+
+        assert!(entries[0].include_subdomains);
+        let c = &entries[0].cookie;
+        assert_eq!(c.name.as_slice(), "session");
+        assert_eq!(c.value.as_slice(), "a b");
+        assert_eq!(c.domain, Some("example.com".to_string()));
+        assert_eq!(c.path, Some("/".to_string()));
+        assert!(!c.secure);
+        assert!(!c.httponly);
+        assert_eq!(c.expires, None);
+
+        assert!(!entries[1].include_subdomains);
+        let c = &entries[1].cookie;
+        assert_eq!(c.domain, Some("www.example.com".to_string()));
+        assert_eq!(c.path, Some("/app".to_string()));
+        assert!(c.secure);
+        assert!(c.httponly);
+        assert_eq!(c.expires, Some(time::at_utc(Timespec::new(2000000000, 0))));
+    }
+
+    #[test]
+    fn round_trip() {
+        let txt = CookiesTxt::parse(FILE).unwrap();
+        assert_eq!(txt.to_string().as_slice(), FILE);
+
+        let crlf = FILE.replace("\n", "\r\n");
+        assert_eq!(CookiesTxt::parse(crlf.as_slice()).unwrap(), txt);
+    }
+
+    #[test]
+    fn write() {
+        let mut txt = CookiesTxt::new();
+        let mut c = Cookie::new("a".to_string(), "1;2".to_string());
+        c.domain = Some(".example.com".to_string());
+        c.expires = Some(time::at_utc(Timespec::new(2000000000, 0)));
+        txt.push(c, true);
+        txt.push_comment("seen");
+        let mut c = Cookie::new("b".to_string(), "".to_string());
+        c.domain = Some("example.com".to_string());
+        c.httponly = true;
+        c.quoted = true;
+        txt.push(c, false);
+
+        assert_eq!(txt.to_string().as_slice(),
+                   "# Netscape HTTP Cookie File\n\
+                    .example.com\tTRUE\t/\tFALSE\t2000000000\ta\t1%3B2\n\
+                    # seen\n\
+                    #HttpOnly_example.com\tFALSE\t/\tFALSE\t0\tb\t\"\"\n");
+
+        let cookies = CookiesTxt::parse(txt.to_string().as_slice()).unwrap().cookies();
+        assert_eq!(cookies[0].value.as_slice(), "1;2");
+        assert_eq!(cookies[1].value.as_slice(), "");
+        assert!(cookies[1].quoted);
+    }
+
+    #[test]
+    fn write_escapes_fields() {
+        let mut txt = CookiesTxt::new();
+        let mut c = Cookie::new("a".to_string(), "1".to_string());
+        c.domain = Some("example.com\tTRUE".to_string());
+        c.path = Some("/a\tb\nevil.com".to_string());
+        txt.push(c, false);
+        assert_eq!(txt.to_string().as_slice(),
+                   "# Netscape HTTP Cookie File\n\
+                    example.com%09TRUE\tFALSE\t/a%09b%0Aevil.com\tFALSE\t0\ta\t1\n");
+        assert_eq!(CookiesTxt::parse(txt.to_string().as_slice()).unwrap().entries().len(), 1);
+    }
+
+    #[test]
+    fn max_age() {
+        let mut txt = CookiesTxt::new();
+        let mut c = Cookie::new("a".to_string(), "1".to_string());
+        c.max_age = Some(60);
+        txt.push(c, false);
+        let c = &txt.entries()[0].cookie;
+        assert_eq!(c.max_age, None);
+        assert!(c.expires.is_some());
+    }
+
+    #[test]
+    fn errors() {
+        let err = CookiesTxt::parse("# ok\nexample.com\tTRUE\t/\n").unwrap_err();
+        assert_eq!(err, CookiesTxtError {
+            line: 2,
+            kind: CookiesTxtErrorKind::FieldCount(3),
+        });
+        assert_eq!(err.to_string().as_slice(),
+                   "line 2: expected 7 tab-separated fields (found 3)");
+
+        let err = CookiesTxt::parse("\n\nexample.com\tyes\t/\tFALSE\t0\ta\tb")
+                             .unwrap_err();
+        assert_eq!(err, CookiesTxtError {
+            line: 3,
+            kind: CookiesTxtErrorKind::InvalidFlag("yes".to_string()),
+        });
+
+        let err = CookiesTxt::parse("example.com\tTRUE\t/\tFALSE\tsoon\ta\tb")
+                             .unwrap_err();
+        assert_eq!(err.kind, CookiesTxtErrorKind::InvalidExpiry("soon".to_string()));
+    }
+}
@@ -0,0 +1,620 @@
+//! Parsers for the `Set-Cookie` and `Cookie` headers.
+//!
+//! `strict`, `lenient` and `legacy` back the `ParseMode` variants accepted by
+//! `Cookie::parse_with`, while `parse_cookie_header` handles the request side.
+
+use std::ascii::AsciiExt;
+use std::borrow::Cow;
+use std::string::CowString;
+use std::i64;
+use url;
+
+use {Cookie, CookieRef, Legacy, ParseError};
+use date::parse_cookie_date;
+
+/// Parses a cookie, failing on the first malformed piece of input.
+///
+/// Nothing is allocated unless the name or value needs percent-decoding or
+/// there are custom attributes.
+pub fn strict<'a>(s: &'a str) -> Result<CookieRef<'a>, ParseError> {
+    let mut pieces = pieces(s);
+    let (offset, keyval) = pieces.next().unwrap();
+    let (name, value) = match split(keyval) {
+        Some(pair) => pair,
+        None => return Err(ParseError::MissingPair {
+            offset: offset,
+            input: keyval.to_string(),
+        }),
+    };
+    let (value, quoted) = unwrap_quotes(value);
+    let value_offset = offset + name.len() + 1 + if quoted { 1 } else { 0 };
+    let mut c = CookieRef {
+        name: try!(decode_cow("name", name, offset)),
+        value: try!(decode_cow("value", value, value_offset)),
+        quoted: quoted,
+        expires: None,
+        max_age: None,
+        domain: None,
+        path: Some("/"),
+        secure: false,
+        httponly: false,
+        same_site: None,
+        partitioned: false,
+        priority: None,
+        custom: Vec::new(),
+    };
+
+    for (offset, attr) in pieces {
+        match attr {
+            "Secure" => c.secure = true,
+            "HttpOnly" => c.httponly = true,
+            _ if attr.eq_ignore_ascii_case("Partitioned") => c.partitioned = true,
+            s => {
+                let (k, v) = match split(s) {
+                    Some(pair) => pair,
+                    None => return Err(ParseError::MissingValue {
+                        attr: s.to_string(),
+                        offset: offset,
+                        input: s.to_string(),
+                    }),
+                };
+                let offset = offset + k.len() + 1;
+                match k {
+                    "Max-Age" => {
+                        c.max_age = match from_str(v) {
+                            Some(n) => Some(n),
+                            None => return Err(ParseError::InvalidMaxAge {
+                                offset: offset,
+                                input: v.to_string(),
+                            }),
+                        };
+                    }
+                    "Domain" => c.domain = Some(v),
+                    "Path" => c.path = Some(v),
+                    "Expires" => {
+                        c.expires = match parse_cookie_date(v) {
+                            Some(tm) => Some(tm),
+                            None => return Err(ParseError::InvalidExpires {
+                                offset: offset,
+                                input: v.to_string(),
+                            }),
+                        };
+                    }
+                    // These are newer than the other attributes, and commonly
+                    // sent in lowercase, so aren't matched case-sensitively.
+                    _ if k.eq_ignore_ascii_case("SameSite") => {
+                        c.same_site = from_str(v);
+                    }
+                    _ if k.eq_ignore_ascii_case("Priority") => {
+                        c.priority = from_str(v);
+                    }
+                    _ => {
+                        c.custom.push((try!(decode_cow(k, k, offset - k.len() - 1)),
+                                       try!(decode_cow(k, v, offset))));
+                    }
+                }
+            }
+        }
+    }
+
+    return Ok(c);
+
+    fn split<'a>(s: &'a str) -> Option<(&'a str, &'a str)> {
+        let mut parts = s.splitn(1, '=');
+        match (parts.next(), parts.next()) {
+            (Some(k), Some(v)) => Some((k, v)),
+            _ => None,
+        }
+    }
+
+    // Only allocates if there is something to decode.
+    fn decode_cow<'a>(attr: &str, s: &'a str, offset: uint)
+                      -> Result<CowString<'a>, ParseError> {
+        if s.contains_char('%') {
+            decode(attr, s, offset).map(Cow::Owned)
+        } else {
+            Ok(Cow::Borrowed(s))
+        }
+    }
+}
+
+/// Parses a cookie following the algorithm of RFC 6265 section 5.2.
+///
+/// The only failures are those for which the RFC says the user agent must
+/// ignore the whole `Set-Cookie` header: a missing `=` in the leading pair, or
+/// an empty name. Attributes with invalid values are skipped, and the
+/// attributes aren't checked against each other. `Cookie::parse_with` also
+/// applies `Limits::check_header`.
+pub fn lenient(s: &str) -> Result<Cookie, ParseError> {
+    // Steps 1-3: split off the name-value-pair at the first `;`, and the name
+    // from the value at the first `=` within it.
+    let (pair, attrs) = match s.find(';') {
+        Some(i) => (s.slice_to(i), s.slice_from(i)),
+        None => (s, ""),
+    };
+    let eq = match pair.find('=') {
+        Some(i) => i,
+        None => return Err(ParseError::MissingPair {
+            offset: 0,
+            input: pair.to_string(),
+        }),
+    };
+
+    // Steps 4-5: trim whitespace and reject an empty name.
+    let name = trim_wsp(pair.slice_to(eq));
+    let value = trim_wsp(pair.slice_from(eq + 1));
+    if name.is_empty() {
+        return Err(ParseError::EmptyName {
+            offset: 0,
+            input: pair.to_string(),
+        })
+    }
+
+    // The default-path depends on the request-uri, which we don't know, so
+    // the path is left unset unless a valid `Path` attribute is present.
+    let (value, quoted) = unwrap_quotes(value);
+    let mut c = Cookie::new(decode_or_raw(name), decode_or_raw(value));
+    c.quoted = quoted;
+    c.path = None;
+
+    // The unparsed-attributes begin with the `;`, so the first piece is empty.
+    for av in attrs.split(';').skip(1) {
+        let (k, v) = match av.find('=') {
+            Some(i) => (trim_wsp(av.slice_to(i)), trim_wsp(av.slice_from(i + 1))),
+            None => (trim_wsp(av), ""),
+        };
+        lenient_attr(&mut c, k, v);
+    }
+    Ok(c)
+}
+
+/// Applies one attribute to `c` as RFC 6265 section 5.2 describes, ignoring
+/// it if its value is invalid.
+fn lenient_attr(c: &mut Cookie, k: &str, v: &str) {
+    if k.eq_ignore_ascii_case("Expires") {
+        // Section 5.2.1
+        match parse_cookie_date(v) {
+            Some(tm) => c.expires = Some(tm),
+            None => {}
+        }
+    } else if k.eq_ignore_ascii_case("Max-Age") {
+        // Section 5.2.2
+        match max_age(v) {
+            Some(n) => c.max_age = Some(n),
+            None => {}
+        }
+    } else if k.eq_ignore_ascii_case("Domain") {
+        // Section 5.2.3
+        if v.is_empty() { return }
+        let v = if v.starts_with(".") { v.slice_from(1) } else { v };
+        c.domain = Some(v.chars().map(|ch| ch.to_lowercase()).collect());
+    } else if k.eq_ignore_ascii_case("Path") {
+        // Section 5.2.4
+        c.path = if v.starts_with("/") { Some(v.to_string()) } else { None };
+    } else if k.eq_ignore_ascii_case("Secure") {
+        // Section 5.2.5
+        c.secure = true;
+    } else if k.eq_ignore_ascii_case("HttpOnly") {
+        // Section 5.2.6
+        c.httponly = true;
+    } else if k.eq_ignore_ascii_case("SameSite") {
+        // RFC 6265bis section 5.6.7
+        c.same_site = from_str(v);
+    } else if k.eq_ignore_ascii_case("Partitioned") {
+        c.partitioned = true;
+    } else if k.eq_ignore_ascii_case("Priority") {
+        c.priority = from_str(v);
+    } else if !k.is_empty() {
+        c.custom.insert(decode_or_raw(k), decode_or_raw(v));
+    }
+
+    // Any value of zero or less expires the cookie immediately, which is
+    // left for `Cookie::expiration` to decide. Numbers too large to represent
+    // saturate.
+    fn max_age(v: &str) -> Option<i64> {
+        let digits = if v.starts_with("-") { v.slice_from(1) } else { v };
+        if digits.is_empty() || !digits.bytes().all(|b| b >= b'0' && b <= b'9') {
+            return None
+        }
+        match from_str(v) {
+            Some(n) => Some(n),
+            None if digits.len() == v.len() => Some(i64::MAX),
+            None => Some(i64::MIN),
+        }
+    }
+}
+
+/// Parses a `Set-Cookie2` cookie following RFC 2965.
+///
+/// Values may be quoted-strings, which are unquoted before anything else
+/// looks at them. The RFC 2965 attributes go into the cookie's `legacy`
+/// field, and everything else is treated as `lenient` treats it.
+pub fn legacy(s: &str) -> Result<Cookie, ParseError> {
+    let avs = quoted_pieces(s);
+    let (offset, pair) = avs[0];
+    let eq = match pair.find('=') {
+        Some(i) => i,
+        None => return Err(ParseError::MissingPair {
+            offset: offset,
+            input: pair.to_string(),
+        }),
+    };
+    let name = trim_wsp(pair.slice_to(eq));
+    if name.is_empty() {
+        return Err(ParseError::EmptyName {
+            offset: offset,
+            input: pair.to_string(),
+        })
+    }
+    let value = trim_wsp(pair.slice_from(eq + 1));
+    let mut c = Cookie::new(decode_or_raw(name), decode_or_raw(unquote(value).as_slice()));
+    c.quoted = value.starts_with("\"");
+    c.path = None;
+
+    let mut legacy = Legacy::default();
+    for &(_, av) in avs.iter().skip(1) {
+        let (k, v) = match av.find('=') {
+            Some(i) => {
+                (trim_wsp(av.slice_to(i)), Some(unquote(trim_wsp(av.slice_from(i + 1)))))
+            }
+            None => (trim_wsp(av), None),
+        };
+        let v = v.as_ref().map(|v| v.as_slice());
+        if k.eq_ignore_ascii_case("Version") {
+            legacy.version = v.and_then(|v| from_str(v));
+        } else if k.eq_ignore_ascii_case("Comment") {
+            legacy.comment = v.map(|v| decode_or_raw(v));
+        } else if k.eq_ignore_ascii_case("CommentURL") {
+            legacy.comment_url = v.map(|v| decode_or_raw(v));
+        } else if k.eq_ignore_ascii_case("Discard") {
+            legacy.discard = true;
+        } else if k.eq_ignore_ascii_case("Port") {
+            // A list with an invalid port is ignored, as the RFC says the
+            // cookie would never match a request anyway.
+            match v {
+                Some(v) => match ports(v) {
+                    Some(ports) => legacy.port = Some(ports),
+                    None => {}
+                },
+                None => legacy.port = Some(Vec::new()),
+            }
+        } else {
+            lenient_attr(&mut c, k, v.unwrap_or(""));
+        }
+    }
+    c.legacy = Some(legacy);
+    return Ok(c);
+
+    fn ports(v: &str) -> Option<Vec<u16>> {
+        v.split(',').map(|p| from_str(p.trim())).collect()
+    }
+}
+
+/// Splits a cookie string like `pieces`, except that a `;` inside a
+/// quoted-string doesn't end a piece.
+fn quoted_pieces(s: &str) -> Vec<(uint, &str)> {
+    split_quoted(s).into_iter().map(|(start, piece)| {
+        let trimmed = piece.trim_left();
+        (start + piece.len() - trimmed.len(), trimmed.trim_right())
+    }).collect()
+}
+
+/// Splits `s` on each `;` which isn't inside a quoted-string, yielding each
+/// untrimmed piece along with the byte offset at which it starts.
+pub fn split_quoted(s: &str) -> Vec<(uint, &str)> {
+    let mut ret = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, b) in s.bytes().enumerate() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' if quoted => escaped = true,
+            b'"' => quoted = !quoted,
+            b';' if !quoted => {
+                ret.push((start, s.slice(start, i)));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    ret.push((start, s.slice_from(start)));
+    ret
+}
+
+/// Removes the quotes and backslash escapes from a quoted-string, returning
+/// any other value unchanged. An unterminated quoted-string runs to the end
+/// of the value.
+fn unquote(s: &str) -> String {
+    if !s.starts_with("\"") {
+        return s.to_string()
+    }
+    let mut ret = String::new();
+    let mut chars = s.slice_from(1).chars();
+    loop {
+        match chars.next() {
+            Some('"') | None => break,
+            Some('\\') => match chars.next() {
+                Some(ch) => ret.push(ch),
+                None => break,
+            },
+            Some(ch) => ret.push(ch),
+        }
+    }
+    ret
+}
+
+/// Parses the value of a request `Cookie` header into name/value pairs.
+///
+/// Pairs are returned in the order they appear, including any duplicate
+/// names. Whitespace around each name and value is removed and empty pieces,
+/// such as those left by a trailing `;`, are skipped. A piece without an `=`
+/// is treated as a value with an empty name, as browsers do. Double quotes
+/// around a value are removed. Names and values are percent-decoded, unless
+/// that doesn't produce valid UTF-8, in which case they are kept as they are:
+/// browsers send whatever other sites on the domain set, so one bad pair
+/// mustn't hide the rest. As a result, parsing can't fail.
+///
+/// # Example
+///
+/// ```
+/// use cookie::parse_cookie_header;
+///
+/// let pairs = parse_cookie_header("a=1; b=2;c=");
+/// assert_eq!(pairs, vec![("a".to_string(), "1".to_string()),
+///                        ("b".to_string(), "2".to_string()),
+///                        ("c".to_string(), "".to_string())]);
+/// ```
+pub fn parse_cookie_header(s: &str) -> Vec<(String, String)> {
+    let mut ret = Vec::new();
+    for (_, piece) in pieces(s) {
+        if piece.is_empty() { continue }
+        let (name, value) = match piece.find('=') {
+            Some(i) => (piece.slice_to(i).trim_right(), piece.slice_from(i + 1).trim_left()),
+            None => ("", piece),
+        };
+        let (value, _) = unwrap_quotes(value);
+        ret.push((decode_or_raw(name), decode_or_raw(value)));
+    }
+    ret
+}
+
+/// Parses several request `Cookie` headers into a single list of name/value
+/// pairs.
+///
+/// HTTP/2 allows the `Cookie` header to be split into one field per pair, so
+/// this is equivalent to parsing the headers joined with `; `.
+pub fn parse_cookie_headers(headers: &[&str]) -> Vec<(String, String)> {
+    let mut ret = Vec::new();
+    for header in headers.iter() {
+        ret.extend(parse_cookie_header(*header).into_iter());
+    }
+    ret
+}
+
+fn decode(attr: &str, s: &str, offset: uint) -> Result<String, ParseError> {
+    String::from_utf8(url::percent_decode(s.as_bytes())).map_err(|_| {
+        ParseError::InvalidUtf8 {
+            attr: attr.to_string(),
+            offset: offset,
+            input: s.to_string(),
+        }
+    })
+}
+
+/// Removes the double quotes which RFC 6265 allows around a cookie value,
+/// returning whether there were any.
+pub fn unwrap_quotes(s: &str) -> (&str, bool) {
+    if s.len() >= 2 && s.starts_with("\"") && s.ends_with("\"") {
+        (s.slice(1, s.len() - 1), true)
+    } else {
+        (s, false)
+    }
+}
+
+/// Percent-decodes `s`, keeping it as it was if the result isn't UTF-8.
+pub fn decode_or_raw(s: &str) -> String {
+    match String::from_utf8(url::percent_decode(s.as_bytes())) {
+        Ok(s) => s,
+        Err(..) => s.to_string(),
+    }
+}
+
+/// Splits a cookie string on `;`, yielding each trimmed piece along with the
+/// byte offset at which it starts in the original string.
+fn pieces(s: &str) -> Pieces {
+    Pieces { s: s, start: 0, done: false }
+}
+
+struct Pieces<'a> {
+    s: &'a str,
+    start: uint,
+    done: bool,
+}
+
+impl<'a> Iterator<(uint, &'a str)> for Pieces<'a> {
+    fn next(&mut self) -> Option<(uint, &'a str)> {
+        if self.done { return None }
+        let rest = self.s.slice_from(self.start);
+        let piece = match rest.find(';') {
+            Some(i) => rest.slice_to(i),
+            None => { self.done = true; rest }
+        };
+        let trimmed = piece.trim_left();
+        let ret = (self.start + piece.len() - trimmed.len(), trimmed.trim_right());
+        self.start += piece.len() + 1;
+        Some(ret)
+    }
+}
+
+/// Removes leading and trailing spaces and horizontal tabs.
+fn trim_wsp(s: &str) -> &str {
+    let bytes = s.as_bytes();
+    let mut start = 0;
+    let mut end = bytes.len();
+    while start < end && is_wsp(bytes[start]) { start += 1 }
+    while end > start && is_wsp(bytes[end - 1]) { end -= 1 }
+    return s.slice(start, end);
+
+    fn is_wsp(b: u8) -> bool { b == b' ' || b == b'\t' }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Cookie, ParseError, ParseMode, SameSite, Priority};
+    use super::{parse_cookie_header, parse_cookie_headers};
+
+    fn lenient(s: &str) -> Result<Cookie, ParseError> {
+        Cookie::parse_with(s, ParseMode::Lenient)
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let c = lenient("foo=bar; secure; HTTPONLY; max-age=4; \
+                         PATH=/foo; domain=Foo.COM").unwrap();
+        assert!(c.secure);
+        assert!(c.httponly);
+        assert_eq!(c.max_age, Some(4));
+        assert_eq!(c.path, Some("/foo".to_string()));
+        assert_eq!(c.domain, Some("foo.com".to_string()));
+        assert!(c.custom.is_empty());
+
+        let c = lenient("foo=bar; SAMESITE=lax").unwrap();
+        assert_eq!(c.same_site, Some(SameSite::Lax));
+        let c = lenient("foo=bar; SameSite=Strict; samesite=").unwrap();
+        assert_eq!(c.same_site, None);
+
+        let c = lenient("foo=bar; secure; partitioned; PRIORITY=medium").unwrap();
+        assert!(c.partitioned);
+        assert_eq!(c.priority, Some(Priority::Medium));
+        let c = lenient("foo=bar; Partitioned").unwrap();
+        assert!(c.partitioned && c.validate().is_err());
+
+        assert!(Cookie::parse("foo=bar; secure").is_err());
+    }
+
+    #[test]
+    fn invalid_attributes_ignored() {
+        let c = lenient("foo=bar; Max-Age=4; Max-Age=abc; Expires=never; \
+                         Path=relative; Domain=; ;").unwrap();
+        assert_eq!(c.name.as_slice(), "foo");
+        assert_eq!(c.value.as_slice(), "bar");
+        assert_eq!(c.max_age, Some(4));
+        assert_eq!(c.expires, None);
+        assert_eq!(c.path, None);
+        assert_eq!(c.domain, None);
+        assert!(c.custom.is_empty());
+    }
+
+    #[test]
+    fn max_age() {
+        assert_eq!(lenient("a=b; Max-Age=-1").unwrap().max_age, Some(-1));
+        assert_eq!(lenient("a=b; Max-Age=0").unwrap().max_age, Some(0));
+        assert_eq!(lenient("a=b; Max-Age=99999999999999999999999").unwrap().max_age,
+                   Some(::std::i64::MAX));
+        assert_eq!(lenient("a=b; Max-Age=-99999999999999999999999").unwrap().max_age,
+                   Some(::std::i64::MIN));
+        assert_eq!(lenient("a=b; Max-Age=+1").unwrap().max_age, None);
+        assert_eq!(lenient("a=b; Max-Age=-").unwrap().max_age, None);
+        assert_eq!(lenient("a=b; Max-Age=1 2").unwrap().max_age, None);
+    }
+
+    #[test]
+    fn name_value_pair() {
+        let c = lenient(" \tfoo \t= bar baz \t;Secure").unwrap();
+        assert_eq!(c.name.as_slice(), "foo");
+        assert_eq!(c.value.as_slice(), "bar baz");
+        assert!(c.secure);
+
+        let c = lenient("foo=").unwrap();
+        assert_eq!(c.value.as_slice(), "");
+
+        let c = lenient("foo=a=b").unwrap();
+        assert_eq!(c.value.as_slice(), "a=b");
+
+        let c = lenient("foo=b%FFr").unwrap();
+        assert_eq!(c.value.as_slice(), "b%FFr");
+
+        let c = lenient("foo= \"bar\" ").unwrap();
+        assert_eq!(c.value.as_slice(), "bar");
+        assert!(c.quoted);
+
+        assert_eq!(lenient("foo; a=b"),
+                   Err(ParseError::MissingPair {
+                       offset: 0,
+                       input: "foo".to_string(),
+                   }));
+        assert_eq!(lenient(" \t=bar"),
+                   Err(ParseError::EmptyName {
+                       offset: 0,
+                       input: " \t=bar".to_string(),
+                   }));
+    }
+
+    #[test]
+    fn expires() {
+        let strict = Cookie::parse("a=b; Expires=Sunday, 06-Nov-94 08:49:37 GMT")
+                           .unwrap();
+        let lenient = lenient("a=b; expires=Sun Nov  6 08:49:37 1994").unwrap();
+        assert!(strict.expires.is_some());
+        assert_eq!(strict.expires, lenient.expires);
+    }
+
+    #[test]
+    fn unknown_attributes() {
+        let c = lenient("foo=bar; Flag; Other = x ").unwrap();
+        assert_eq!(c.custom.get(&"Flag".to_string()), Some(&"".to_string()));
+        assert_eq!(c.custom.get(&"Other".to_string()), Some(&"x".to_string()));
+    }
+
+    #[test]
+    fn legacy() {
+        let legacy = |s: &str| Cookie::parse_with(s, ParseMode::Legacy).unwrap();
+
+        let c = legacy("a=\"x\\\\y\"; VERSION=1; comment=\"a;b\"; x=\"1\"");
+        assert_eq!(c.value.as_slice(), "x\\y");
+        assert!(c.quoted);
+        assert_eq!(c.path, None);
+        let l = c.legacy.unwrap();
+        assert_eq!(l.version, Some(1));
+        assert_eq!(l.comment, Some("a;b".to_string()));
+        assert_eq!(c.custom.get(&"x".to_string()), Some(&"1".to_string()));
+
+        // Unterminated quotes run to the end, and bad values are ignored.
+        let c = legacy("a=\"x; Discard");
+        assert_eq!(c.value.as_slice(), "x; Discard");
+        assert!(!c.legacy.unwrap().discard);
+        let l = legacy("a=b; Version=one; Port=\"80,http\"").legacy.unwrap();
+        assert_eq!(l.version, None);
+        assert_eq!(l.port, None);
+
+        assert_eq!(Cookie::parse_with("=b; Version=1", ParseMode::Legacy),
+                   Err(ParseError::EmptyName { offset: 0, input: "=b".to_string() }));
+    }
+
+    fn pairs(v: &[(&str, &str)]) -> Vec<(String, String)> {
+        v.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn cookie_header() {
+        assert_eq!(parse_cookie_header("a=1; b=2; c=3"),
+                   pairs(&[("a", "1"), ("b", "2"), ("c", "3")]));
+        assert_eq!(parse_cookie_header("  a = 1 ;;b=;  ; a=2;"),
+                   pairs(&[("a", "1"), ("b", ""), ("a", "2")]));
+        assert_eq!(parse_cookie_header("a=x=y; lonely; c=%2F"),
+                   pairs(&[("a", "x=y"), ("", "lonely"), ("c", "/")]));
+        assert_eq!(parse_cookie_header(""), pairs(&[]));
+        assert_eq!(parse_cookie_header("a=\"1 2\"; b=\""),
+                   pairs(&[("a", "1 2"), ("b", "\"")]));
+
+        assert_eq!(parse_cookie_header("a=1; tracker= %FF; b=%2"),
+                   pairs(&[("a", "1"), ("tracker", "%FF"), ("b", "%2")]));
+    }
+
+    #[test]
+    fn cookie_headers() {
+        assert_eq!(parse_cookie_headers(&["a=1", "b=2; a=3", ""]),
+                   pairs(&[("a", "1"), ("b", "2"), ("a", "3")]));
+    }
+}
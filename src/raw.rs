@@ -0,0 +1,259 @@
+//! Set-Cookie headers which keep their original text.
+//!
+//! Printing a parsed `Cookie` normalizes it: attributes are reordered, their
+//! names are recased and the value is percent-encoded again. A `RawCookie`
+//! instead remembers the header exactly as it was received and only rewrites
+//! the attributes which are changed through it, which is what a proxy needs
+//! when it forwards `Set-Cookie` headers from upstream.
+
+use std::ascii::AsciiExt;
+use std::fmt;
+
+use {Cookie, ParseError, ParseMode, ValidationError};
+use builder::is_token_char;
+use parse::split_quoted;
+
+/// A parsed `Set-Cookie` header which prints exactly as it was received,
+/// apart from any attributes modified through it.
+///
+/// # Example
+///
+/// ```
+/// use cookie::{RawCookie, ParseMode};
+///
+/// let s = "id=a%20b; path=/; DOMAIN=upstream.example; secure";
+/// let mut c = RawCookie::parse(s, ParseMode::Lenient).unwrap();
+/// c.set_domain(Some("proxy.example")).unwrap();
+/// assert_eq!(c.to_string().as_slice(),
+///            "id=a%20b; path=/; DOMAIN=proxy.example; secure");
+/// assert_eq!(c.cookie().domain, Some("proxy.example".to_string()));
+/// ```
+#[deriving(PartialEq, Clone)]
+pub struct RawCookie {
+    cookie: Cookie,
+    mode: ParseMode,
+    // The text between each `;`, including surrounding whitespace. The first
+    // piece is the `name=value` pair. In legacy mode a `;` inside a
+    // quoted-string doesn't split pieces.
+    pieces: Vec<String>,
+}
+
+impl RawCookie {
+    /// Parses a `Set-Cookie` header value, keeping its original text.
+    pub fn parse(s: &str, mode: ParseMode) -> Result<RawCookie, ParseError> {
+        let pieces = match mode {
+            ParseMode::Legacy => {
+                split_quoted(s).into_iter().map(|(_, s)| s.to_string()).collect()
+            }
+            ParseMode::Strict | ParseMode::Lenient => {
+                s.split(';').map(|s| s.to_string()).collect()
+            }
+        };
+        Ok(RawCookie {
+            cookie: try!(Cookie::parse_with(s, mode.clone())),
+            mode: mode,
+            pieces: pieces,
+        })
+    }
+
+    /// Returns the cookie as it is currently parsed.
+    pub fn cookie(&self) -> &Cookie {
+        &self.cookie
+    }
+
+    /// Sets every attribute named `name`, ignoring case, to `value`.
+    ///
+    /// Attributes keep their position and the case of their name. If there is
+    /// no such attribute, `; name=value` is appended. The name must be a
+    /// token, and the value is written exactly as given, so it may not
+    /// contain `;` or control characters.
+    pub fn set_attr(&mut self, name: &str, value: &str) -> Result<(), ParseError> {
+        try!(check_name(name));
+        if value.contains_char(';') || value.chars().any(|ch| ch.is_control()) {
+            return Err(ParseError::Invalid {
+                error: ValidationError::InvalidAttr(name.to_string()),
+                input: value.to_string(),
+            })
+        }
+        let mut pieces = self.pieces.clone();
+        let mut found = false;
+        for piece in pieces.iter_mut().skip(1) {
+            if !is_attr(piece.as_slice(), name) { continue }
+            let written = match piece.as_slice().find('=') {
+                Some(i) => piece.as_slice().slice_to(i).to_string(),
+                None => piece.as_slice().trim_right().to_string(),
+            };
+            *piece = format!("{}={}", written, value);
+            found = true;
+        }
+        if !found {
+            pieces.push(format!(" {}={}", name, value));
+        }
+        self.update(pieces)
+    }
+
+    /// Sets or clears a flag attribute such as `Secure`, ignoring case.
+    ///
+    /// A flag which is already present is left as it was written, and a
+    /// missing one is appended. The name must be a token.
+    pub fn set_flag(&mut self, name: &str, on: bool) -> Result<(), ParseError> {
+        try!(check_name(name));
+        if !on {
+            return self.remove_attr(name)
+        }
+        if self.pieces.iter().skip(1).any(|p| is_attr(p.as_slice(), name)) {
+            return Ok(())
+        }
+        let mut pieces = self.pieces.clone();
+        pieces.push(format!(" {}", name));
+        self.update(pieces)
+    }
+
+    /// Removes every attribute named `name`, ignoring case.
+    pub fn remove_attr(&mut self, name: &str) -> Result<(), ParseError> {
+        try!(check_name(name));
+        let mut pieces = Vec::new();
+        for (i, piece) in self.pieces.iter().enumerate() {
+            if i == 0 || !is_attr(piece.as_slice(), name) {
+                pieces.push(piece.clone());
+            }
+        }
+        self.update(pieces)
+    }
+
+    /// Sets the `Domain` attribute, or removes it if `None` is given.
+    pub fn set_domain(&mut self, domain: Option<&str>) -> Result<(), ParseError> {
+        match domain {
+            Some(domain) => self.set_attr("Domain", domain),
+            None => self.remove_attr("Domain"),
+        }
+    }
+
+    /// Sets the `Path` attribute, or removes it if `None` is given.
+    pub fn set_path(&mut self, path: Option<&str>) -> Result<(), ParseError> {
+        match path {
+            Some(path) => self.set_attr("Path", path),
+            None => self.remove_attr("Path"),
+        }
+    }
+
+    // Reparses the modified text, leaving `self` unchanged if it's invalid.
+    fn update(&mut self, pieces: Vec<String>) -> Result<(), ParseError> {
+        let text = pieces.connect(";");
+        self.cookie = try!(Cookie::parse_with(text.as_slice(), self.mode.clone()));
+        self.pieces = pieces;
+        Ok(())
+    }
+}
+
+/// Checks that the attribute name `name` is a token, so that it can't add
+/// attributes of its own when it's written.
+fn check_name(name: &str) -> Result<(), ParseError> {
+    if name.is_empty() || !name.chars().all(|ch| is_token_char(ch)) {
+        return Err(ParseError::Invalid {
+            error: ValidationError::InvalidAttr(name.to_string()),
+            input: name.to_string(),
+        })
+    }
+    Ok(())
+}
+
+/// Returns whether the attribute text `piece` has the name `name`, ignoring
+/// case and surrounding whitespace.
+fn is_attr(piece: &str, name: &str) -> bool {
+    let written = match piece.find('=') {
+        Some(i) => piece.slice_to(i),
+        None => piece,
+    };
+    written.trim().eq_ignore_ascii_case(name)
+}
+
+impl fmt::Show for RawCookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pieces.connect(";"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {ParseMode, RawCookie};
+
+    const UPSTREAM: &'static str = "id=a%20b;path=/app; DOMAIN=upstream.example \
+                                    ;Max-Age=60; secure; HttpOnly; x=%22y%22";
+
+    #[test]
+    fn unchanged() {
+        let c = RawCookie::parse(UPSTREAM, ParseMode::Lenient).unwrap();
+        assert_eq!(c.to_string().as_slice(), UPSTREAM);
+        assert_eq!(c.cookie().value.as_slice(), "a b");
+
+        let c = RawCookie::parse(" a=b ; Path=/ ", ParseMode::Strict).unwrap();
+        assert_eq!(c.to_string().as_slice(), " a=b ; Path=/ ");
+    }
+
+    #[test]
+    fn set_domain() {
+        let mut c = RawCookie::parse(UPSTREAM, ParseMode::Lenient).unwrap();
+        c.set_domain(Some("proxy.example")).unwrap();
+        assert_eq!(c.to_string().as_slice(),
+                   "id=a%20b;path=/app; DOMAIN=proxy.example;Max-Age=60; \
+                    secure; HttpOnly; x=%22y%22");
+        assert_eq!(c.cookie().domain, Some("proxy.example".to_string()));
+
+        c.set_domain(None).unwrap();
+        assert_eq!(c.to_string().as_slice(),
+                   "id=a%20b;path=/app;Max-Age=60; secure; HttpOnly; x=%22y%22");
+        assert_eq!(c.cookie().domain, None);
+
+        c.set_domain(Some("other.example")).unwrap();
+        assert_eq!(c.to_string().as_slice(),
+                   "id=a%20b;path=/app;Max-Age=60; secure; HttpOnly; x=%22y%22; \
+                    Domain=other.example");
+    }
+
+    #[test]
+    fn flags() {
+        let mut c = RawCookie::parse("a=b; secure", ParseMode::Lenient).unwrap();
+        c.set_flag("Secure", true).unwrap();
+        assert_eq!(c.to_string().as_slice(), "a=b; secure");
+        c.set_flag("HttpOnly", true).unwrap();
+        assert_eq!(c.to_string().as_slice(), "a=b; secure; HttpOnly");
+        assert!(c.cookie().httponly);
+        c.set_flag("secure", false).unwrap();
+        assert_eq!(c.to_string().as_slice(), "a=b; HttpOnly");
+        assert!(!c.cookie().secure);
+    }
+
+    #[test]
+    fn legacy() {
+        let s = "a=\"x; path=/old\"; Comment=\"b; Path=/c\"; Path=/d; Version=1";
+        let mut c = RawCookie::parse(s, ParseMode::Legacy).unwrap();
+        c.set_path(Some("/new")).unwrap();
+        assert_eq!(c.to_string().as_slice(),
+                   "a=\"x; path=/old\"; Comment=\"b; Path=/c\"; Path=/new; Version=1");
+        assert_eq!(c.cookie().value.as_slice(), "x; path=/old");
+        assert_eq!(c.cookie().path, Some("/new".to_string()));
+    }
+
+    #[test]
+    fn invalid() {
+        let mut c = RawCookie::parse("a=b; Path=/", ParseMode::Strict).unwrap();
+        assert!(c.set_path(Some("/; Domain=evil.example")).is_err());
+        assert!(c.set_path(Some("/\r\nX-Injected: 1")).is_err());
+        assert!(c.set_attr("Max-Age", "soon").is_err());
+        assert_eq!(c.to_string().as_slice(), "a=b; Path=/");
+    }
+
+    #[test]
+    fn invalid_name() {
+        let mut c = RawCookie::parse("a=b; Path=/", ParseMode::Strict).unwrap();
+        assert!(c.set_attr("Path; Domain", "evil.example").is_err());
+        assert!(c.set_attr("Domain=evil.example; x", "y").is_err());
+        assert!(c.set_attr("", "y").is_err());
+        assert!(c.set_flag("Secure; Domain=evil.example", true).is_err());
+        assert!(c.set_flag("Secure\r\nX-Injected: 1", true).is_err());
+        assert!(c.remove_attr("Path; Domain").is_err());
+        assert_eq!(c.to_string().as_slice(), "a=b; Path=/");
+        assert_eq!(c.cookie().domain, None);
+    }
+}
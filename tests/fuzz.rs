@@ -0,0 +1,224 @@
+//! Randomized tests for the parsers, the `Show` implementation and the signed,
+//! encrypted and private jars.
+//!
+//! Every test runs a fixed number of cases from a fixed seed, so a failure
+//! can be reproduced by running it again. Set `COOKIE_FUZZ_ITERS` to run more
+//! cases, and `COOKIE_FUZZ_SEED` to explore different ones.
+
+extern crate cookie;
+extern crate time;
+
+use std::os;
+use std::rand::{Rng, SeedableRng, XorShiftRng};
+use time::Timespec;
+
+use cookie::{Cookie, CookieRef, CookieJar, CookieHeader, RawCookie};
+use cookie::{ParseMode, SameSite, Priority};
+use cookie::{parse_cookie_header, parse_cookie_headers};
+
+// Characters chosen to hit every escaping and splitting rule: separators,
+// whitespace, controls, `%` and non-ASCII.
+static CHARS: &'static [char] = &[
+    'a', 'b', 'Z', '0', '9', '-', '_', '.', '/', '~', '!', '*', '\'', '+',
+    ' ', '\t', '\r', '\n', '\0', '\x7f', ';', ',', '=', '"', '\\', '%', '&',
+    '?', '#', '<', '>', '{', '}', '@', ':', '[', ']', '|', '`', '^',
+    'é', '☃', '\U0001F36A',
+];
+
+static DOMAIN_CHARS: &'static [char] = &['a', 'z', 'Q', '0', '7', '-', '.'];
+
+// Fragments of `Set-Cookie` syntax, so that random input gets past the name
+// and value often enough to exercise attribute parsing.
+static FRAGMENTS: &'static [&'static str] = &[
+    "=", ";", "; ", "%", "%2", "%FF", "%C3%A9", "Secure", "HttpOnly", "Path=",
+    "Domain=", "Max-Age=", "Max-Age=-", "Expires=", "SameSite=", "Priority=",
+    "Partitioned", "Sun, 06 Nov 1994 08:49:37 GMT", "99999999999999999999",
+    "__Secure-", "__Host-", "\"", "--", "=;=", "\\", "Version=1", "Comment=",
+    "Port", "Port=\"80,", "Discard",
+];
+
+fn rng() -> XorShiftRng {
+    let seed = os::getenv("COOKIE_FUZZ_SEED").and_then(|s| from_str(s.as_slice()))
+                                             .unwrap_or(0x5eed);
+    SeedableRng::from_seed([seed, 0x193a6754, 0xa8a7d469, 0x97830e05])
+}
+
+fn iters() -> uint {
+    os::getenv("COOKIE_FUZZ_ITERS").and_then(|s| from_str(s.as_slice()))
+                                   .unwrap_or(1000)
+}
+
+fn string<R: Rng>(rng: &mut R, chars: &[char], min: uint, max: uint) -> String {
+    let len = rng.gen_range(min, max + 1);
+    range(0, len).map(|_| *rng.choose(chars).unwrap()).collect()
+}
+
+// Like `string`, but without the control characters a cookie can't contain.
+fn text<R: Rng>(rng: &mut R, min: uint, max: uint) -> String {
+    let mut s = string(rng, CHARS, min, max).as_slice().chars()
+                    .filter(|ch| !ch.is_control()).collect::<String>();
+    while s.len() < min { s.push('a'); }
+    s
+}
+
+fn garbage<R: Rng>(rng: &mut R) -> String {
+    let mut s = String::new();
+    for _ in range(0, rng.gen_range(0u, 12)) {
+        if rng.gen() {
+            s.push_str(*rng.choose(FRAGMENTS).unwrap());
+        } else {
+            s.push_str(string(rng, CHARS, 1, 4).as_slice());
+        }
+    }
+    s
+}
+
+// A cookie which survives being written and parsed again in strict mode.
+fn cookie<R: Rng>(rng: &mut R) -> Cookie {
+    let mut name = text(rng, 1, 10);
+    while name.as_slice().starts_with("__") {
+        name = text(rng, 1, 10);
+    }
+    let mut c = Cookie::new(name, text(rng, 0, 20));
+    c.quoted = rng.gen();
+
+    if rng.gen() {
+        // Any time from 1970 to 9999, in whole seconds.
+        let secs = rng.gen_range(0, 253402300800i64);
+        c.expires = Some(time::at_utc(Timespec::new(secs, 0)));
+    }
+    if rng.gen() {
+        c.max_age = Some(rng.gen());
+    }
+    if rng.gen() {
+        c.domain = Some(string(rng, DOMAIN_CHARS, 1, 15));
+    }
+    // Paths are written as they are, so they can't end in whitespace, which
+    // is trimmed, and only `/` is implied when there's no `Path`.
+    let path = text(rng, 0, 10).as_slice().chars().filter(|ch| {
+        (*ch as u32) < 0x80 && *ch != ';'
+    }).collect::<String>();
+    let mut path = format!("/{}", path);
+    if path.as_slice().ends_with(" ") { path.push('x'); }
+    c.path = Some(path);
+
+    c.secure = rng.gen();
+    c.httponly = rng.gen();
+    c.same_site = *rng.choose(&[None, Some(SameSite::Strict), Some(SameSite::Lax),
+                                Some(SameSite::None)]).unwrap();
+    c.partitioned = c.secure && rng.gen();
+    c.priority = *rng.choose(&[None, Some(Priority::Low), Some(Priority::Medium),
+                               Some(Priority::High)]).unwrap();
+    for _ in range(0, rng.gen_range(0u, 3)) {
+        // The prefix keeps these from being taken for known attributes.
+        let name = format!("x-{}", text(rng, 0, 5));
+        c.custom.insert(name, text(rng, 0, 10));
+    }
+    c
+}
+
+#[test]
+fn parse_never_panics() {
+    let mut rng = rng();
+    for _ in range(0, iters()) {
+        let s = garbage(&mut rng);
+        let s = s.as_slice();
+        let _ = Cookie::parse(s);
+        let _ = Cookie::parse_with(s, ParseMode::Lenient);
+        let _ = Cookie::parse_with(s, ParseMode::Legacy);
+        let _ = CookieRef::parse(s);
+        let _ = RawCookie::parse(s, ParseMode::Strict);
+        let _ = RawCookie::parse(s, ParseMode::Lenient);
+        let _ = RawCookie::parse(s, ParseMode::Legacy);
+        let _ = parse_cookie_header(s);
+        let _ = parse_cookie_headers(&[s, s]);
+    }
+}
+
+#[test]
+fn show_parse_round_trip() {
+    let mut rng = rng();
+    for _ in range(0, iters()) {
+        let c = cookie(&mut rng);
+        let s = c.to_string();
+        assert_eq!(Cookie::parse(s.as_slice()), Ok(c.clone()));
+        assert_eq!(CookieRef::parse(s.as_slice()).map(|c| c.into_owned()), Ok(c.clone()));
+
+        // The lenient parser also has to accept anything we write.
+        let lenient = Cookie::parse_with(s.as_slice(), ParseMode::Lenient).unwrap();
+        assert_eq!(lenient.name, c.name);
+        assert_eq!(lenient.value, c.value);
+    }
+}
+
+#[test]
+fn request_header_round_trip() {
+    let mut rng = rng();
+    for _ in range(0, iters()) {
+        let cookies = range(0, rng.gen_range(0u, 4)).map(|_| {
+            let mut c = cookie(&mut rng);
+            c.name = string(&mut rng, &['a', 'Z', '0', '-', '!', '%', '~'], 1, 6);
+            c
+        }).collect::<Vec<_>>();
+        let header = CookieHeader::new(cookies.as_slice()).render().unwrap();
+        let pairs = cookies.iter().map(|c| (c.name.clone(), c.value.clone()))
+                           .collect::<Vec<_>>();
+        assert_eq!(parse_cookie_header(header.as_slice()), pairs);
+    }
+}
+
+#[test]
+fn secure_never_panics() {
+    const KEY: &'static [u8] = b"f8f9eaf1ecdedff5e5b749c58115441e";
+    let mut rng = rng();
+    for _ in range(0, iters()) {
+        let c = Cookie::new("a".to_string(), text(&mut rng, 0, 20));
+        let sealed = CookieJar::new(KEY);
+        sealed.signed().add(c.clone());
+        let signed = sealed.find("a").unwrap().value;
+        sealed.encrypted().add(c.clone());
+        let encrypted = sealed.find("a").unwrap().value;
+        sealed.private().add(c.clone());
+        let private = sealed.find("a").unwrap().value;
+
+        // Genuine values come back out.
+        let mut jar = CookieJar::new(KEY);
+        jar.add_original(Cookie::new("a".to_string(), signed.clone()));
+        assert_eq!(jar.signed().find("a").map(|c| c.value), Some(c.value.clone()));
+        let mut jar = CookieJar::new(KEY);
+        jar.add_original(Cookie::new("a".to_string(), encrypted.clone()));
+        assert_eq!(jar.encrypted().find("a").map(|c| c.value), Some(c.value.clone()));
+        let mut jar = CookieJar::new(KEY);
+        jar.add_original(Cookie::new("a".to_string(), private.clone()));
+        assert_eq!(jar.private().find("a").map(|c| c.value), Some(c.value.clone()));
+
+        // Garbage, and genuine values which have been tampered with, are
+        // rejected without panicking.
+        let mut values = vec![garbage(&mut rng)];
+        for v in [signed, encrypted].iter() {
+            // Both end in a hex-encoded HMAC-SHA1, so the last 40 bytes are
+            // ASCII and safe to change or cut at.
+            let mut bytes = v.clone().into_bytes();
+            let i = rng.gen_range(bytes.len() - 40, bytes.len());
+            bytes[i] = *rng.choose(b"0123456789abcdef-xz").unwrap();
+            values.push(String::from_utf8(bytes).unwrap());
+            let cut = rng.gen_range(v.len() - 40, v.len());
+            values.push(v.as_slice().slice_to(cut).to_string());
+            values.push(format!("{}--{}", garbage(&mut rng), v));
+        }
+        // Private values are all base64url, so they can be changed or cut
+        // anywhere.
+        let mut bytes = private.clone().into_bytes();
+        let i = rng.gen_range(0, bytes.len());
+        bytes[i] = if bytes[i] == b'A' { b'B' } else { b'A' };
+        values.push(String::from_utf8(bytes).unwrap());
+        values.push(private.as_slice().slice_to(rng.gen_range(0, private.len())).to_string());
+        for v in values.into_iter() {
+            let mut jar = CookieJar::new(KEY);
+            jar.add_original(Cookie::new("a".to_string(), v));
+            let _ = jar.signed().find("a");
+            let _ = jar.encrypted().find("a");
+            let _ = jar.private().find("a");
+        }
+    }
+}